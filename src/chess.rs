@@ -1,47 +1,196 @@
-use egui::{Align2, Color32, Context, Frame, ImageButton, Pos2, Ui};
-use shakmaty::{fen::Fen, san::San, Chess, Color, Move, Outcome, Piece, Position, Role, Square};
+use std::fmt::Display;
 
+use egui::{Align2, Color32, Context, Frame, ImageButton, Pos2, Rect, RichText, Stroke, Ui};
+use shakmaty::{
+    fen::Fen,
+    san::{San, SanError, SanPlus},
+    uci::Uci,
+    CastlingMode, Chess, Color, EnPassantMode, Move, Outcome, Piece, Position, Role, Square,
+};
+
+mod analysis;
+mod animation;
+mod clock;
+mod editor;
+mod history;
+mod pgn;
+mod premove;
 mod utils;
 
+pub(crate) use analysis::Analysis;
+use analysis::Suggestion;
+use animation::{ease, MoveAnimation};
+pub(crate) use clock::TimeControl;
+use clock::{format_clock, ChessClock};
+pub(crate) use editor::BoardEditor;
+use history::*;
+pub(crate) use pgn::{parse_pgn, GameTags, ImportedGame};
+use premove::{board_after, Premove, PremoveSelection};
+
 use tokio::sync::mpsc;
 use utils::*;
 use web_types::{EngineVariant, GameMoveResponse};
 
-use crate::requests::RequestLoopComm;
+use crate::requests::{
+    next_game_id, request_channel, send_request, MoveHistory, PendingRequest, PosEvalRequest,
+    RequestLoopComm,
+};
 
 #[derive(Debug)]
 pub(crate) struct AiGameSettings {
-    engine_move_receiver: Option<oneshot::Receiver<anyhow::Result<GameMoveResponse>>>,
+    /// Name of the engine and variant, as used in exported games
+    name: String,
+    engine_move_receiver: Option<PendingRequest<GameMoveResponse>>,
+    draw_offer_receiver: Option<PendingRequest<bool>>,
     ai_variant: EngineVariant,
+    /// Send the starting position and all moves, not just the current position
+    send_move_history: bool,
     sender: mpsc::Sender<crate::requests::RequestLoopComm>,
 }
 
 impl AiGameSettings {
     pub fn new(
+        name: String,
         variant: EngineVariant,
+        send_move_history: bool,
         sender: mpsc::Sender<crate::requests::RequestLoopComm>,
     ) -> Self {
-        log::info!("Reconfiguring AiGameSettings: variant: {variant:?}");
+        log::info!("Reconfiguring AiGameSettings: variant: {variant:?}, send_move_history: {send_move_history}");
         AiGameSettings {
+            name,
             engine_move_receiver: None,
+            draw_offer_receiver: None,
             ai_variant: variant,
+            send_move_history,
             sender,
         }
     }
+
+    /// Forget the outstanding requests, so that a late reply
+    /// can never be applied to a different position.
+    fn cancel_requests(&mut self) {
+        if let Some(request) = self.engine_move_receiver.take() {
+            request.cancel();
+            log::info!("Cancelled pending AI move");
+        }
+        self.draw_offer_receiver = None;
+    }
+}
+
+/// Two engines playing each other while the user watches.
+#[derive(Debug)]
+pub(crate) struct AiVsAiSettings {
+    white: AiGameSettings,
+    black: AiGameSettings,
+    /// No moves are requested while paused, except for a single step
+    paused: bool,
+    step: bool,
+    /// When the last move was played, the next one is requested after the move delay
+    last_move_time: f64,
+}
+
+impl AiVsAiSettings {
+    pub fn new(white: AiGameSettings, black: AiGameSettings) -> Self {
+        Self {
+            white,
+            black,
+            paused: false,
+            step: false,
+            last_move_time: 0.0,
+        }
+    }
+}
+
+/// How to answer a draw offer when the engine cannot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DrawOfferFallback {
+    Decline,
+    Accept,
+    /// Accept unless the engine is ahead in material
+    Material,
+}
+
+impl Display for DrawOfferFallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DrawOfferFallback::Decline => write!(f, "Always decline"),
+            DrawOfferFallback::Accept => write!(f, "Always accept"),
+            DrawOfferFallback::Material => write!(f, "Accept unless ahead in material"),
+        }
+    }
+}
+
+/// What to do when the engine replies with a move that cannot be played.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum InvalidMoveHandling {
+    /// The engine loses the game
+    Forfeit,
+    /// Ask the engine again, a few times before it forfeits
+    Requery,
+}
+
+impl Display for InvalidMoveHandling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidMoveHandling::Forfeit => write!(f, "Engine forfeits"),
+            InvalidMoveHandling::Requery => write!(f, "Ask the engine again"),
+        }
+    }
+}
+
+/// Times the engine is asked again after an invalid move before it forfeits anyway.
+const MAX_REQUERIES: u32 = 3;
+
+/// An engine reply that could not be played, kept to show what went wrong.
+pub(crate) struct InvalidAiMove {
+    pub error: String,
+    pub response: GameMoveResponse,
 }
 
 pub(crate) enum GameMode {
     PlayAgainsAI(AiGameSettings),
+    AiVsAi(Box<AiVsAiSettings>),
     PlayAgainsYourself,
+    /// Both sides are moved by the user, with the engine's suggestion shown
+    Analysis(Box<Analysis>),
 }
 
 impl PartialEq for GameMode {
     fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (GameMode::PlayAgainsAI(_), GameMode::PlayAgainsAI(_)) => true,
-            (GameMode::PlayAgainsAI(_), GameMode::PlayAgainsYourself) => false,
-            (GameMode::PlayAgainsYourself, GameMode::PlayAgainsAI(_)) => false,
-            (GameMode::PlayAgainsYourself, GameMode::PlayAgainsYourself) => true,
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+impl GameMode {
+    /// Settings of the engine playing `color`, if it is not played by the user.
+    fn engine(&self, color: Color, player_color: Color) -> Option<&AiGameSettings> {
+        match self {
+            GameMode::PlayAgainsAI(settings) => (color != player_color).then_some(settings),
+            GameMode::AiVsAi(settings) => Some(match color {
+                Color::White => &settings.white,
+                Color::Black => &settings.black,
+            }),
+            GameMode::PlayAgainsYourself | GameMode::Analysis(_) => None,
+        }
+    }
+
+    fn engine_mut(&mut self, color: Color, player_color: Color) -> Option<&mut AiGameSettings> {
+        match self {
+            GameMode::PlayAgainsAI(settings) => (color != player_color).then_some(settings),
+            GameMode::AiVsAi(settings) => Some(match color {
+                Color::White => &mut settings.white,
+                Color::Black => &mut settings.black,
+            }),
+            GameMode::PlayAgainsYourself | GameMode::Analysis(_) => None,
+        }
+    }
+
+    /// Settings of every engine taking part in the game.
+    fn engines_mut(&mut self) -> Vec<&mut AiGameSettings> {
+        match self {
+            GameMode::PlayAgainsAI(settings) => vec![settings],
+            GameMode::AiVsAi(settings) => vec![&mut settings.white, &mut settings.black],
+            GameMode::PlayAgainsYourself | GameMode::Analysis(_) => Vec::new(),
         }
     }
 }
@@ -76,9 +225,11 @@ impl PieceSelection {
     }
 }
 
-struct LastMove {
-    a: Square,
-    b: Square,
+/// A piece being dragged across the board.
+#[derive(Clone, Copy)]
+struct DragState {
+    from: Square,
+    piece: Piece,
 }
 
 struct PromotionData {
@@ -93,9 +244,50 @@ pub(crate) struct ChessBoard {
     pub(crate) player_color: Color,
     pub(crate) game_mode: GameMode,
     selection: Option<PieceSelection>,
-    last_move: Option<LastMove>,
-    last_ai_move: Option<GameMoveResponse>,
+    drag: Option<DragState>,
+    /// Moves to play as soon as the AI has moved, the next one first
+    premoves: Vec<Premove>,
+    premove_selection: Option<PremoveSelection>,
+    /// Screen area of every square, as drawn in the last frame
+    square_rects: Vec<(Square, Rect)>,
+    /// Identifies the current game to the request loop
+    game_id: u64,
+    /// Position the current game was started from
+    start_position: Chess,
+    /// Players and date of the current game, recorded when it started
+    tags: GameTags,
+    history: Vec<HistoryEntry>,
+    /// Moves taken back by undo, the next one to redo is last
+    redo_stack: Vec<HistoryEntry>,
+    /// Number of plies shown on the board while browsing the history,
+    /// `None` when showing the live position.
+    viewed_ply: Option<usize>,
     promotion: PromotionData,
+    /// Move typed in by the player, and why it could not be played
+    move_input: String,
+    move_input_error: Option<String>,
+    animation: Option<MoveAnimation>,
+    /// How long a move takes to slide into place in seconds, 0 turns animations off
+    pub(crate) animation_duration: f64,
+    /// Seconds between the moves of a game between two engines, so it can be followed
+    pub(crate) move_delay: f64,
+    /// Used for the clocks of the next game, untimed if `None`
+    pub(crate) time_control: Option<TimeControl>,
+    clock: Option<ChessClock>,
+    /// Current time in seconds, as reported by egui
+    time: f64,
+    /// How the game ended, if it was not decided on the board
+    termination: Option<Termination>,
+    /// Used when the engine cannot answer a draw offer itself
+    pub(crate) draw_offer_fallback: DrawOfferFallback,
+    draw_offer_status: Option<&'static str>,
+    /// Why the game is paused after the AI failed to move
+    ai_move_error: Option<String>,
+    pub(crate) invalid_move_handling: InvalidMoveHandling,
+    /// The last reply of the engine that could not be played
+    invalid_ai_move: Option<InvalidAiMove>,
+    /// Times the engine was asked again since its last valid move
+    requeries: u32,
     game_is_going: bool,
     game_over_is_dismissed: bool,
 }
@@ -107,14 +299,37 @@ impl Default for ChessBoard {
             player_color: Color::White,
             game_mode: GameMode::PlayAgainsYourself,
             selection: None,
-            last_move: None,
-            last_ai_move: None,
+            drag: None,
+            premoves: Vec::new(),
+            premove_selection: None,
+            square_rects: Vec::new(),
+            game_id: next_game_id(),
+            start_position: Chess::default(),
+            tags: GameTags::default(),
+            history: Vec::new(),
+            redo_stack: Vec::new(),
+            viewed_ply: None,
             promotion: PromotionData {
                 show_promotion_choice: false,
                 promotion_panel_anchor_pos: Default::default(),
                 color: None,
                 promotion_move: None,
             },
+            move_input: String::new(),
+            move_input_error: None,
+            animation: None,
+            animation_duration: 0.2,
+            move_delay: 1.0,
+            time_control: None,
+            clock: None,
+            time: 0.0,
+            termination: None,
+            draw_offer_fallback: DrawOfferFallback::Material,
+            draw_offer_status: None,
+            ai_move_error: None,
+            invalid_move_handling: InvalidMoveHandling::Forfeit,
+            invalid_ai_move: None,
+            requeries: 0,
             game_is_going: false,
             game_over_is_dismissed: false,
         }
@@ -135,6 +350,42 @@ pub(crate) enum Termination {
     /// Draw
     InsufficientMaterial,
 
+    /// The same position occurred three times and a player claimed a draw
+    /// Draw
+    ThreefoldRepetition,
+
+    /// The same position occurred five times
+    /// Draw
+    FivefoldRepetition,
+
+    /// Fifty moves without a capture or pawn move and a player claimed a draw
+    /// Draw
+    FiftyMoveRule,
+
+    /// Seventy-five moves without a capture or pawn move
+    /// Draw
+    SeventyFiveMoveRule,
+
+    /// {color} resigned
+    /// {color} loses
+    Resignation(Color),
+
+    /// Both sides agreed to a draw
+    /// Draw
+    DrawAgreed,
+
+    /// {color} ran out of time
+    /// {color} loses
+    Timeout(Color),
+
+    /// {color} ran out of time, but the opponent cannot checkmate
+    /// Draw
+    TimeoutVsInsufficientMaterial(Color),
+
+    /// {color} is an engine that replied with an invalid move
+    /// {color} loses
+    EngineForfeit(Color),
+
     /// Unknown type of termination
     Unknown(Outcome),
 }
@@ -145,44 +396,388 @@ impl Termination {
             Termination::Checkmate(c) => Outcome::Decisive { winner: c.other() },
             Termination::Stalemate(_) => Outcome::Draw,
             Termination::InsufficientMaterial => Outcome::Draw,
+            Termination::ThreefoldRepetition => Outcome::Draw,
+            Termination::FivefoldRepetition => Outcome::Draw,
+            Termination::FiftyMoveRule => Outcome::Draw,
+            Termination::SeventyFiveMoveRule => Outcome::Draw,
+            Termination::Resignation(c) => Outcome::Decisive { winner: c.other() },
+            Termination::DrawAgreed => Outcome::Draw,
+            Termination::Timeout(c) => Outcome::Decisive { winner: c.other() },
+            Termination::TimeoutVsInsufficientMaterial(_) => Outcome::Draw,
+            Termination::EngineForfeit(c) => Outcome::Decisive { winner: c.other() },
             Termination::Unknown(v) => v,
         }
     }
+
+    pub fn description(&self) -> &'static str {
+        match *self {
+            Termination::Checkmate(Color::Black) => "Black to move and is in checkmate",
+            Termination::Checkmate(Color::White) => "White to move and is in checkmate",
+            Termination::Stalemate(Color::Black) => "Black to move and is stalemated",
+            Termination::Stalemate(Color::White) => "White to move and is stalemated",
+            Termination::InsufficientMaterial => "Draw due to insufficient material",
+            Termination::ThreefoldRepetition => "Draw claimed by threefold repetition",
+            Termination::FivefoldRepetition => "Draw by fivefold repetition",
+            Termination::FiftyMoveRule => {
+                "Draw claimed after fifty moves without a capture or pawn move"
+            }
+            Termination::SeventyFiveMoveRule => {
+                "Draw after seventy-five moves without a capture or pawn move"
+            }
+            Termination::Resignation(Color::Black) => "Black resigned",
+            Termination::Resignation(Color::White) => "White resigned",
+            Termination::DrawAgreed => "Draw by agreement",
+            Termination::Timeout(Color::Black) => "Black ran out of time",
+            Termination::Timeout(Color::White) => "White ran out of time",
+            Termination::TimeoutVsInsufficientMaterial(Color::Black) => {
+                "Black ran out of time, but White cannot checkmate"
+            }
+            Termination::TimeoutVsInsufficientMaterial(Color::White) => {
+                "White ran out of time, but Black cannot checkmate"
+            }
+            Termination::EngineForfeit(Color::Black) => {
+                "Black forfeited by replying with an invalid move"
+            }
+            Termination::EngineForfeit(Color::White) => {
+                "White forfeited by replying with an invalid move"
+            }
+            Termination::Unknown(_) => "The game is over",
+        }
+    }
 }
 
 impl ChessBoard {
+    /// Start a new game from the position the current one was started from.
     pub(crate) fn start_game(&mut self) {
-        self.chess = Chess::default();
-        self.selection = None;
-        self.last_move = None;
-        self.last_ai_move = None;
-        self.game_is_going = true;
+        self.start_game_from(self.start_position.clone());
+    }
+
+    /// Start a new game from `position`, with either side to move.
+    pub(crate) fn start_game_from(&mut self, position: Chess) {
+        self.reset_to(position);
+        self.record_tags();
+        // Analysis is untimed
+        self.clock = self
+            .time_control
+            .filter(|_| !self.is_analysis())
+            .map(ChessClock::new);
+        if let GameMode::AiVsAi(settings) = &mut self.game_mode {
+            settings.paused = false;
+            settings.step = false;
+            settings.last_move_time = 0.0;
+        }
+        self.game_is_going = self.get_termination().is_none();
         self.game_over_is_dismissed = false;
     }
 
+    /// Forget everything about the current game, leaving a new one at `position`
+    /// with no moves played, for [`Self::start_game_from`] and [`Self::load_game`].
+    fn reset_to(&mut self, position: Chess) {
+        self.cancel_ai_move();
+        self.chess = position;
+        self.start_position = self.chess.clone();
+        self.game_id = next_game_id();
+        self.selection = None;
+        self.drag = None;
+        self.animation = None;
+        self.history.clear();
+        self.redo_stack.clear();
+        self.viewed_ply = None;
+        self.termination = None;
+        self.invalid_ai_move = None;
+        self.requeries = 0;
+    }
+
+    /// Remember who plays the current game and when it started, for exporting it.
+    /// Changing the settings afterwards does not relabel the game.
+    fn record_tags(&mut self) {
+        let name = |color: Color| {
+            self.game_mode
+                .engine(color, self.player_color)
+                .map_or_else(|| "Player".to_string(), |settings| settings.name.clone())
+        };
+        self.tags = GameTags {
+            white: name(Color::White),
+            black: name(Color::Black),
+            date: pgn::today(),
+        };
+    }
+
+    pub fn tags(&self) -> &GameTags {
+        &self.tags
+    }
+
     pub fn stop_game(&mut self) {
         self.promotion.show_promotion_choice = false;
         self.promotion.promotion_move = None;
         self.promotion.color = None;
+        self.cancel_ai_move();
         self.game_is_going = false;
     }
 
     pub fn last_ai_move_info(&mut self) -> Option<GameMoveResponse> {
-        self.last_ai_move.clone()
+        self.history
+            .iter()
+            .rev()
+            .find_map(|entry| entry.ai_response.clone())
+    }
+
+    /// The position currently shown on the board,
+    /// which is an earlier one while browsing the history.
+    fn displayed_position(&self) -> &Chess {
+        match self.viewed_ply {
+            Some(0) => &self.start_position,
+            Some(ply) => &self.history[ply - 1].position,
+            None => &self.chess,
+        }
+    }
+
+    fn displayed_last_move(&self) -> Option<LastMove> {
+        let ply = self.viewed_ply.unwrap_or(self.history.len());
+        ply.checked_sub(1).map(|idx| self.history[idx].last_move())
+    }
+
+    /// Show the position after `ply` half-moves, read-only.
+    /// Showing the latest position returns the board to the live game.
+    fn view_ply(&mut self, ply: usize) {
+        self.selection = None;
+        self.drag = None;
+        self.animation = None;
+        self.viewed_ply = (ply < self.history.len()).then_some(ply);
+    }
+
+    /// Number of plies taken back by [`Self::undo`]: one in a game against yourself,
+    /// between engines or in analysis, and back to the player's previous turn
+    /// in a game against the AI.
+    fn undo_plies(&self) -> usize {
+        match self.game_mode {
+            GameMode::PlayAgainsYourself | GameMode::AiVsAi(_) | GameMode::Analysis(_) => {
+                self.history.len().min(1)
+            }
+            GameMode::PlayAgainsAI(_) => self
+                .history
+                .iter()
+                .rposition(|entry| entry.color == self.player_color)
+                .map_or(0, |idx| self.history.len() - idx),
+        }
+    }
+
+    /// Number of plies put back by [`Self::redo`]: the player's move,
+    /// and in a game against the AI also the engine's reply to it.
+    fn redo_plies(&self) -> usize {
+        match self.game_mode {
+            GameMode::PlayAgainsYourself | GameMode::AiVsAi(_) | GameMode::Analysis(_) => {
+                self.redo_stack.len().min(1)
+            }
+            GameMode::PlayAgainsAI(_) => {
+                let mut next = self.redo_stack.iter().rev();
+                match (next.next(), next.next()) {
+                    (Some(_), Some(reply)) if reply.color != self.player_color => 2,
+                    (Some(_), _) => 1,
+                    (None, _) => 0,
+                }
+            }
+        }
+    }
+
+    /// Undo and redo are only available in a game that has been started.
+    fn game_was_started(&self) -> bool {
+        self.game_is_going || self.get_termination().is_some()
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.game_was_started() && self.undo_plies() > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.game_was_started() && self.redo_plies() > 0
+    }
+
+    /// Take moves back. A game between engines is paused,
+    /// or they would play the same moves again right away.
+    pub fn undo(&mut self) {
+        if !self.can_undo() {
+            return;
+        }
+        self.set_paused(true);
+        for _ in 0..self.undo_plies() {
+            if let Some(entry) = self.history.pop() {
+                self.redo_stack.push(entry);
+            }
+        }
+        self.restore_from_history();
+    }
+
+    pub fn redo(&mut self) {
+        if !self.can_redo() {
+            return;
+        }
+        self.set_paused(true);
+        for _ in 0..self.redo_plies() {
+            if let Some(entry) = self.redo_stack.pop() {
+                self.history.push(entry);
+            }
+        }
+        self.restore_from_history();
+    }
+
+    /// Reset the live position and the clocks to the end of the history
+    /// after moves were taken back or put back.
+    fn restore_from_history(&mut self) {
+        self.cancel_ai_move();
+        self.chess = self.history.last().map_or_else(
+            || self.start_position.clone(),
+            |entry| entry.position.clone(),
+        );
+        self.clock = match self.history.last() {
+            Some(entry) => entry.clock.clone(),
+            None => self.clock.as_ref().map(ChessClock::restarted),
+        };
+        self.selection = None;
+        self.drag = None;
+        self.animation = None;
+        self.viewed_ply = None;
+        self.promotion.show_promotion_choice = false;
+        self.promotion.promotion_move = None;
+        self.promotion.color = None;
+        self.termination = None;
+        self.invalid_ai_move = None;
+        self.requeries = 0;
+        self.game_is_going = self.get_termination().is_none();
+        self.game_over_is_dismissed = false;
+    }
+
+    /// Replace the current game with an imported one,
+    /// shown from its first position for replaying.
+    pub fn load_game(&mut self, game: ImportedGame) {
+        self.stop_game();
+        self.reset_to(game.start_position);
+        self.tags = game.tags;
+        // Imported games are untimed
+        self.clock = None;
+        for m in &game.moves {
+            self.play_move(m, MoveAuthor::Imported, None);
+        }
+        self.animation = None;
+        self.game_is_going = false;
+        // A finished game should not greet the user with the game over popup.
+        self.game_over_is_dismissed = true;
+        self.view_ply(0);
+    }
+
+    /// Continue a loaded game from its last position.
+    /// Clocks are not restored, so it continues untimed.
+    pub fn resume_game(&mut self) {
+        self.viewed_ply = None;
+        self.clock = None;
+        self.game_is_going = self.get_termination().is_none();
+    }
+
+    /// Whether the game is still to be played on, including one paused by a failed AI move.
+    pub fn is_in_progress(&self) -> bool {
+        self.game_is_going || self.ai_move_error.is_some()
+    }
+
+    /// How the game ended, if it was not decided on the board.
+    pub fn declared_termination(&self) -> Option<Termination> {
+        self.termination
+    }
+
+    /// Mark a loaded game as ended the way it was when it was saved.
+    pub fn restore_termination(&mut self, termination: Termination) {
+        self.termination = Some(termination);
+        self.game_is_going = false;
+    }
+
+    /// Whether there is a game worth saving, going or finished.
+    pub fn has_game(&self) -> bool {
+        self.game_is_going || !self.history.is_empty()
+    }
+
+    /// A new game can be branched off a position from the history
+    /// when browsing it, or when no game is going.
+    pub fn can_play_from_displayed_position(&self) -> bool {
+        !self.history.is_empty() && (self.viewed_ply.is_some() || !self.game_is_going)
+    }
+
+    /// Start a new game from the position shown on the board,
+    /// discarding any moves after it.
+    pub fn play_from_displayed_position(&mut self) {
+        let ply = self.viewed_ply.unwrap_or(self.history.len());
+        self.history.truncate(ply);
+        self.redo_stack.clear();
+        self.record_tags();
+        self.restore_from_history();
+    }
+
+    /// Forget the outstanding engine request, so that a late reply
+    /// can never be applied to a different position.
+    fn cancel_ai_move(&mut self) {
+        for ai_game_settings in self.game_mode.engines_mut() {
+            ai_game_settings.cancel_requests();
+        }
+        if let GameMode::Analysis(analysis) = &mut self.game_mode {
+            analysis.reset();
+        }
+        self.draw_offer_status = None;
+        self.ai_move_error = None;
+        self.premoves.clear();
+        self.premove_selection = None;
     }
 
     pub fn get_termination(&self) -> Option<Termination> {
+        if self.termination.is_some() {
+            return self.termination;
+        }
         Some(if self.chess.is_insufficient_material() {
             Termination::InsufficientMaterial
         } else if self.chess.is_checkmate() {
             Termination::Checkmate(self.chess.turn())
         } else if self.chess.is_stalemate() {
             Termination::Stalemate(self.chess.turn())
+        } else if let Some(outcome) = self.chess.outcome() {
+            Termination::Unknown(outcome)
+        } else if self.repetition_count() >= 5 {
+            Termination::FivefoldRepetition
+        } else if self.chess.halfmoves() >= 150 {
+            Termination::SeventyFiveMoveRule
         } else {
-            Termination::Unknown(self.chess.outcome()?)
+            return None;
         })
     }
 
+    /// How many times the current position has occurred in the game, counting this time.
+    fn repetition_count(&self) -> usize {
+        let start = position_hash(&self.start_position);
+        let current = self.history.last().map_or(start, |entry| entry.hash);
+        std::iter::once(start)
+            .chain(self.history.iter().map(|entry| entry.hash))
+            .filter(|hash| *hash == current)
+            .count()
+    }
+
+    /// The draw the side to move may claim right now, if any.
+    /// Against the AI only the player can claim.
+    pub fn claimable_draw(&self) -> Option<Termination> {
+        if !self.game_is_going || self.viewed_ply.is_some() || !self.is_human(self.chess.turn()) {
+            return None;
+        }
+        if self.repetition_count() >= 3 {
+            Some(Termination::ThreefoldRepetition)
+        } else if self.chess.halfmoves() >= 100 {
+            Some(Termination::FiftyMoveRule)
+        } else {
+            None
+        }
+    }
+
+    pub fn claim_draw(&mut self) {
+        if let Some(termination) = self.claimable_draw() {
+            log::info!("Draw claimed: {termination:?}");
+            self.end_game(termination);
+        }
+    }
+
     pub fn game_over_is_dismissed(&self) -> bool {
         self.game_over_is_dismissed
     }
@@ -190,77 +785,546 @@ impl ChessBoard {
         self.game_over_is_dismissed = true;
     }
 
-    fn play_move(&mut self, m: &Move) {
+    fn play_move(&mut self, m: &Move, author: MoveAuthor, ai_response: Option<GameMoveResponse>) {
+        let color = self.chess.turn();
+        self.animation = (self.animation_duration > 0.0)
+            .then(|| MoveAnimation::new(m, self.chess.board(), color));
         // We can use `play_unchecked` because only the legal
         // squares ever become interactable
-        self.chess.play_unchecked(m);
+        let san = SanPlus::from_move_and_play_unchecked(&mut self.chess, m);
         log::debug!("Move played: {m:?}");
         if m.is_en_passant() {
             log::warn!("Holy Hell!");
         }
-        self.last_move = Some(if let Move::Castle { king, .. } = m {
-            LastMove {
-                a: *king,
-                b: m.castling_side()
-                    .unwrap()
-                    .king_to(self.selection.as_ref().unwrap().piece.color),
-            }
-        } else {
-            LastMove {
-                a: m.from().unwrap(),
-                b: m.to(),
-            }
+        if let Some(clock) = &mut self.clock {
+            clock.switch(self.time);
+        }
+        self.history.push(HistoryEntry {
+            m: m.clone(),
+            san,
+            position: self.chess.clone(),
+            fen: Fen::from_position(self.chess.clone(), EnPassantMode::Legal),
+            hash: position_hash(&self.chess),
+            color,
+            author,
+            ai_response,
+            clock: self.clock.as_ref().map(|clock| clock.stopped(self.time)),
         });
+        self.redo_stack.clear();
         self.selection = None;
+        self.premove_selection = None;
+        self.drag = None;
 
         // If the game is now over, then it is not going.
-        if self.chess.is_game_over() {
+        if self.get_termination().is_some() {
             self.game_is_going = false;
         }
     }
 
+    /// Run the clock of the side to move, and end the game when its flag falls.
+    /// Clocks do not run while a game between engines waits for its next move.
+    pub fn update_clock(&mut self, ctx: &Context) {
+        self.time = ctx.input(|i| i.time);
+        let holds_moves = self.holds_moves();
+        let Some(clock) = &mut self.clock else {
+            return;
+        };
+        if !self.game_is_going || holds_moves {
+            clock.stop(self.time);
+            return;
+        }
+
+        let turn = self.chess.turn();
+        if clock.running_side() != Some(turn) {
+            clock.start(turn, self.time);
+        }
+        if clock.remaining(turn, self.time).is_zero() {
+            clock.stop(self.time);
+            log::info!("{turn:?} ran out of time");
+            self.end_game(if self.chess.has_insufficient_material(turn.other()) {
+                Termination::TimeoutVsInsufficientMaterial(turn)
+            } else {
+                Termination::Timeout(turn)
+            });
+        } else {
+            ctx.request_repaint_after(std::time::Duration::from_millis(100));
+        }
+    }
+
+    pub fn show_clocks(&self, ui: &mut Ui) {
+        let Some(clock) = &self.clock else {
+            return;
+        };
+        egui::Grid::new("clocks").show(ui, |ui| {
+            for (color, name) in [(Color::White, "White"), (Color::Black, "Black")] {
+                let time = RichText::new(format_clock(clock.remaining(color, self.time)))
+                    .monospace()
+                    .size(24.0);
+                ui.label(name);
+                if clock.running_side() == Some(color) {
+                    ui.label(time.strong());
+                } else {
+                    ui.label(time.weak());
+                }
+                ui.end_row();
+            }
+        });
+    }
+
     pub fn is_waiting_for_ai_move(&self) -> bool {
-        if let GameMode::PlayAgainsAI(ai_game_settings) = &self.game_mode {
-            return ai_game_settings.engine_move_receiver.is_some();
+        [Color::White, Color::Black].into_iter().any(|color| {
+            self.game_mode
+                .engine(color, self.player_color)
+                .is_some_and(|settings| settings.engine_move_receiver.is_some())
+        })
+    }
+
+    /// Whether `color` is played by the user rather than an engine.
+    fn is_human(&self, color: Color) -> bool {
+        self.game_mode.engine(color, self.player_color).is_none()
+    }
+
+    /// What the engine playing `color` is told about the live position.
+    fn pos_eval_request(&self, color: Color) -> Option<PosEvalRequest> {
+        let ai_game_settings = self.game_mode.engine(color, self.player_color)?;
+        Some(PosEvalRequest {
+            game_id: self.game_id,
+            variant: ai_game_settings.ai_variant.clone(),
+            fen: Fen::from_position(self.chess.clone(), EnPassantMode::Legal),
+            clock: self.clock.as_ref().map(|clock| clock.info(self.time)),
+            history: ai_game_settings
+                .send_move_history
+                .then(|| self.move_history()),
+        })
+    }
+
+    /// The starting position and the moves played since.
+    pub fn move_history(&self) -> MoveHistory {
+        MoveHistory {
+            start_fen: Fen::from_position(self.start_position.clone(), EnPassantMode::Legal),
+            moves: self
+                .history
+                .iter()
+                .map(|entry| entry.m.to_uci(CastlingMode::Standard))
+                .collect(),
         }
+    }
 
-        false
+    /// The player resigns, or in a game against yourself the side to move does.
+    pub fn resign(&mut self) {
+        if !self.game_is_going || !self.has_player() {
+            return;
+        }
+        let color = match self.game_mode {
+            GameMode::PlayAgainsAI(_) => self.player_color,
+            _ => self.chess.turn(),
+        };
+        log::info!("{color:?} resigned");
+        self.end_game(Termination::Resignation(color));
     }
 
-    pub fn update_ai_move(&mut self) {
-        if self.chess.turn() == self.player_color
-            || self.game_mode == GameMode::PlayAgainsYourself
-            || !self.game_is_going
-        {
+    /// Offer the AI a draw, or agree to one in a game against yourself.
+    pub fn offer_draw(&mut self) {
+        if !self.game_is_going || !self.has_player() {
             return;
         }
+        // Only games against the AI have someone to ask
+        let Some(req) = self.pos_eval_request(self.player_color.other()) else {
+            self.end_game(Termination::DrawAgreed);
+            return;
+        };
         if let GameMode::PlayAgainsAI(ai_game_settings) = &mut self.game_mode {
+            let (responder, receiver) = request_channel();
+            send_request(
+                &ai_game_settings.sender,
+                RequestLoopComm::OfferDraw(req, responder),
+            );
+            ai_game_settings.draw_offer_receiver = Some(receiver);
+            self.draw_offer_status = Some("Waiting for the engine to answer the draw offer...");
+        }
+    }
+
+    pub fn game_is_going(&self) -> bool {
+        self.game_is_going
+    }
+
+    pub fn is_analysis(&self) -> bool {
+        matches!(self.game_mode, GameMode::Analysis(_))
+    }
+
+    /// Whether the user plays in this game, and so can resign or offer a draw.
+    pub fn has_player(&self) -> bool {
+        matches!(
+            self.game_mode,
+            GameMode::PlayAgainsAI(_) | GameMode::PlayAgainsYourself
+        )
+    }
+
+    pub fn can_offer_draw(&self) -> bool {
+        let offer_pending = match &self.game_mode {
+            GameMode::PlayAgainsAI(ai_game_settings) => {
+                ai_game_settings.draw_offer_receiver.is_some()
+            }
+            GameMode::AiVsAi(_) | GameMode::Analysis(_) => return false,
+            GameMode::PlayAgainsYourself => false,
+        };
+        self.game_is_going && !offer_pending
+    }
+
+    pub fn draw_offer_status(&self) -> Option<&'static str> {
+        self.draw_offer_status
+    }
+
+    /// Apply the engine's answer to a draw offer, once it arrives.
+    pub fn update_draw_offer(&mut self) {
+        let GameMode::PlayAgainsAI(ai_game_settings) = &mut self.game_mode else {
+            return;
+        };
+        let Some(receiver) = &ai_game_settings.draw_offer_receiver else {
+            return;
+        };
+        let result = match receiver.try_recv() {
+            Err(oneshot::TryRecvError::Empty) => return,
+            result => result,
+        };
+        ai_game_settings.draw_offer_receiver = None;
+
+        let accepted = match result {
+            Ok(Ok(accepted)) => accepted,
+            Ok(Err(e)) => {
+                log::warn!("Engine could not answer the draw offer: {e}");
+                self.fallback_accepts_draw()
+            }
+            Err(_) => self.fallback_accepts_draw(),
+        };
+        if !self.game_is_going {
+            self.draw_offer_status = None;
+        } else if accepted {
+            self.draw_offer_status = None;
+            self.end_game(Termination::DrawAgreed);
+        } else {
+            self.draw_offer_status = Some("The draw offer was declined");
+        }
+    }
+
+    /// Decide on a draw offer for an engine that cannot do it itself.
+    fn fallback_accepts_draw(&self) -> bool {
+        match self.draw_offer_fallback {
+            DrawOfferFallback::Decline => false,
+            DrawOfferFallback::Accept => true,
+            DrawOfferFallback::Material => {
+                let board = self.chess.board();
+                let material = |color: Color| -> usize {
+                    [
+                        (Role::Pawn, 1),
+                        (Role::Knight, 3),
+                        (Role::Bishop, 3),
+                        (Role::Rook, 5),
+                        (Role::Queen, 9),
+                    ]
+                    .into_iter()
+                    .map(|(role, value)| {
+                        (board.by_color(color) & board.by_role(role)).count() * value
+                    })
+                    .sum()
+                };
+                // The engine takes the draw unless it is ahead
+                material(self.player_color.other()) <= material(self.player_color)
+            }
+        }
+    }
+
+    /// End the game for a reason that is not on the board.
+    fn end_game(&mut self, termination: Termination) {
+        self.termination = Some(termination);
+        self.game_is_going = false;
+        self.cancel_ai_move();
+    }
+
+    pub fn update_ai_move(&mut self, ctx: &Context) {
+        let turn = self.chess.turn();
+        if self.is_human(turn) || !self.game_is_going {
+            return;
+        }
+        if let Some(delay) = self.remaining_move_delay() {
+            ctx.request_repaint_after(delay);
+        }
+        if self.holds_moves() {
+            return;
+        }
+        let request = if self.is_waiting_for_ai_move() {
+            None
+        } else {
+            self.pos_eval_request(turn)
+        };
+        if let Some(ai_game_settings) = self.game_mode.engine_mut(turn, self.player_color) {
             if let Some(move_receiver) = &ai_game_settings.engine_move_receiver {
-                if let Ok(Ok(m)) = move_receiver.try_recv() {
-                    ai_game_settings.engine_move_receiver = None;
-                    self.play_move(
-                        &San::from_ascii(m.move_san.as_bytes())
-                            .unwrap()
-                            .to_move(&self.chess)
-                            .unwrap(),
-                    );
-                    self.last_ai_move = Some(m);
+                match move_receiver.try_recv() {
+                    Err(oneshot::TryRecvError::Empty) => {}
+                    Ok(Ok(response)) => {
+                        ai_game_settings.engine_move_receiver = None;
+                        match parse_engine_move(&self.chess, &response.move_san) {
+                            Ok(m) => {
+                                // A requery succeeded, the rejected reply is history
+                                self.requeries = 0;
+                                self.invalid_ai_move = None;
+                                self.play_move(&m, MoveAuthor::Ai, Some(response));
+                                if let GameMode::AiVsAi(settings) = &mut self.game_mode {
+                                    settings.last_move_time = self.time;
+                                    settings.step = false;
+                                }
+                                self.play_premove();
+                            }
+                            Err(error) => self.reject_ai_move(error, response),
+                        }
+                    }
+                    Ok(Err(e)) => {
+                        ai_game_settings.engine_move_receiver = None;
+                        self.pause_for_ai_error(format!("Could not get the AI's move: {e:#}"));
+                    }
+                    Err(oneshot::TryRecvError::Disconnected) => {
+                        ai_game_settings.engine_move_receiver = None;
+                        self.pause_for_ai_error(
+                            "The request for the AI's move was dropped".to_string(),
+                        );
+                    }
                 }
-            } else {
-                let fen = Fen::from_position(self.chess.clone(), shakmaty::EnPassantMode::Legal);
-                let (sender, receiver) = oneshot::channel();
-                let req =
-                    RequestLoopComm::FetchPosEval(ai_game_settings.ai_variant.clone(), fen, sender);
-                ai_game_settings
-                    .sender
-                    .try_send(req)
-                    .expect("error communicating with request loop");
+            } else if let Some(req) = request {
+                let (responder, receiver) = request_channel();
+                let req = RequestLoopComm::FetchPosEval(req, responder);
+                send_request(&ai_game_settings.sender, req);
                 ai_game_settings.engine_move_receiver = Some(receiver);
             }
         }
     }
 
+    /// Whether a game between engines holds off asking for its next move,
+    /// because it is paused or the move delay has not passed yet.
+    fn holds_moves(&self) -> bool {
+        matches!(&self.game_mode, GameMode::AiVsAi(settings) if settings.paused && !settings.step)
+            || self.remaining_move_delay().is_some()
+    }
+
+    /// How long a game between engines still waits after the last move.
+    fn remaining_move_delay(&self) -> Option<std::time::Duration> {
+        let GameMode::AiVsAi(settings) = &self.game_mode else {
+            return None;
+        };
+        let remaining = settings.last_move_time + self.move_delay - self.time;
+        (!settings.step && remaining > 0.0).then(|| std::time::Duration::from_secs_f64(remaining))
+    }
+
+    /// Whether a game between engines is paused.
+    pub fn is_paused(&self) -> bool {
+        matches!(&self.game_mode, GameMode::AiVsAi(settings) if settings.paused)
+    }
+
+    /// Pause or resume a game between engines. A move already requested is
+    /// only played once the game is resumed.
+    pub fn set_paused(&mut self, paused: bool) {
+        if let GameMode::AiVsAi(settings) = &mut self.game_mode {
+            settings.paused = paused;
+            settings.step = false;
+        }
+    }
+
+    /// Let a paused game between engines play one move.
+    pub fn step(&mut self) {
+        if let GameMode::AiVsAi(settings) = &mut self.game_mode {
+            settings.step = settings.paused;
+        }
+    }
+
+    /// Pause, resume and step buttons for a game between engines.
+    pub fn show_ai_vs_ai_controls(&mut self, ui: &mut Ui) {
+        if !matches!(self.game_mode, GameMode::AiVsAi(_)) {
+            return;
+        }
+        ui.horizontal(|ui| {
+            let paused = self.is_paused();
+            let label = if paused { "▶ Resume" } else { "⏸ Pause" };
+            if ui
+                .add_enabled(self.game_is_going, egui::Button::new(label))
+                .clicked()
+            {
+                self.set_paused(!paused);
+            }
+            if ui
+                .add_enabled(self.game_is_going && paused, egui::Button::new("Step"))
+                .on_hover_text("Play the next move, then pause again")
+                .clicked()
+            {
+                self.step();
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Move delay");
+            ui.add(
+                egui::DragValue::new(&mut self.move_delay)
+                    .clamp_range(0.0..=60.0)
+                    .speed(0.1)
+                    .suffix(" s"),
+            );
+        });
+    }
+
+    /// In analysis mode, ask the engine about the position shown whenever it changes,
+    /// and keep its reply if it is still about that position.
+    pub fn update_analysis(&mut self) {
+        let GameMode::Analysis(analysis) = &self.game_mode else {
+            return;
+        };
+        let position = self.displayed_position().clone();
+        let key = (
+            position_hash(&position),
+            self.viewed_ply.unwrap_or(self.history.len()),
+        );
+        if analysis.position != Some(key) {
+            let request = (self.game_was_started() && !position.is_game_over())
+                .then(|| self.analysis_request(&analysis.settings));
+            let GameMode::Analysis(analysis) = &mut self.game_mode else {
+                unreachable!()
+            };
+            // Replacing the outstanding request cancels it
+            analysis.reset();
+            analysis.generation += 1;
+            analysis.position = Some(key);
+            analysis.request = request.map(|req| {
+                let (responder, receiver) = request_channel();
+                send_request(
+                    &analysis.settings.sender,
+                    RequestLoopComm::FetchPosEval(req, responder),
+                );
+                (analysis.generation, receiver)
+            });
+            return;
+        }
+
+        let GameMode::Analysis(analysis) = &mut self.game_mode else {
+            return;
+        };
+        let Some((generation, receiver)) = &analysis.request else {
+            return;
+        };
+        let result = match receiver.try_recv() {
+            Err(oneshot::TryRecvError::Empty) => return,
+            result => result,
+        };
+        let generation = *generation;
+        analysis.request = None;
+        if generation != analysis.generation {
+            log::debug!("Discarding the analysis of an earlier position");
+            return;
+        }
+        match result {
+            Ok(Ok(response)) => match parse_engine_move(&position, &response.move_san) {
+                Ok(m) => {
+                    analysis.suggestion = Some(Suggestion {
+                        san: SanPlus::from_move(position, &m).to_string(),
+                        m,
+                        response,
+                    });
+                }
+                Err(error) => analysis.error = Some(error),
+            },
+            Ok(Err(e)) => {
+                log::error!("Could not analyse the position: {e:#}");
+                analysis.error = Some(format!("Could not analyse the position: {e:#}"));
+            }
+            Err(oneshot::TryRecvError::Disconnected) => {
+                analysis.error = Some("The request for the analysis was dropped".to_string());
+            }
+        }
+    }
+
+    /// What the engine is told about the position shown, in analysis mode.
+    fn analysis_request(&self, settings: &AiGameSettings) -> PosEvalRequest {
+        let ply = self.viewed_ply.unwrap_or(self.history.len());
+        PosEvalRequest {
+            game_id: self.game_id,
+            variant: settings.ai_variant.clone(),
+            fen: Fen::from_position(self.displayed_position().clone(), EnPassantMode::Legal),
+            clock: None,
+            history: settings.send_move_history.then(|| {
+                let mut history = self.move_history();
+                history.moves.truncate(ply);
+                history
+            }),
+        }
+    }
+
+    /// The engine's suggestion for the position shown, in analysis mode.
+    pub fn show_analysis(&mut self, ui: &mut Ui) {
+        let GameMode::Analysis(analysis) = &mut self.game_mode else {
+            return;
+        };
+        ui.heading("Analysis");
+        if analysis.request.is_some() {
+            ui.horizontal(|ui| {
+                ui.label("Analysing the position...");
+                ui.spinner();
+            });
+        }
+        if let Some(err) = &analysis.error {
+            if crate::show_error_with_retry(ui, err) {
+                analysis.reset();
+            }
+        }
+        if let Some(suggestion) = &analysis.suggestion {
+            egui::Grid::new("analysis_table").show(ui, |ui| {
+                ui.label("Suggested move");
+                ui.label(RichText::new(&suggestion.san).strong());
+                ui.end_row();
+                ui.label("Time taken for computation");
+                ui.label(format!("{:?}", suggestion.response.move_timing));
+                ui.end_row();
+                ui.label("Info");
+                ui.add(egui::Label::new(&suggestion.response.status_text).wrap(true));
+                ui.end_row();
+            });
+        }
+    }
+
+    /// Deal with an engine reply that cannot be played, as set by [`Self::invalid_move_handling`].
+    fn reject_ai_move(&mut self, error: String, response: GameMoveResponse) {
+        log::error!("Invalid AI move: {error}, response: {response:?}");
+        self.invalid_ai_move = Some(InvalidAiMove { error, response });
+        if self.invalid_move_handling == InvalidMoveHandling::Requery
+            && self.requeries < MAX_REQUERIES
+        {
+            // The next update sends the request again
+            self.requeries += 1;
+        } else {
+            self.end_game(Termination::EngineForfeit(self.chess.turn()));
+        }
+    }
+
+    pub fn invalid_ai_move(&self) -> Option<&InvalidAiMove> {
+        self.invalid_ai_move.as_ref()
+    }
+
+    /// Stop the game, and the clocks with it, until the player retries the AI's move.
+    fn pause_for_ai_error(&mut self, error: String) {
+        log::error!("{error}");
+        self.ai_move_error = Some(error);
+        self.game_is_going = false;
+    }
+
+    pub fn ai_move_error(&self) -> Option<&str> {
+        self.ai_move_error.as_deref()
+    }
+
+    /// Continue a game paused by a failed AI move, asking the AI again.
+    pub fn retry_ai_move(&mut self) {
+        if self.ai_move_error.take().is_some() {
+            self.game_is_going = self.get_termination().is_none();
+        }
+    }
+
     pub fn show(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
+        let position = self.displayed_position().clone();
+        let last_move = self.displayed_last_move();
+        self.square_rects.clear();
         egui::Grid::new("chess_board")
             .spacing([0f32, 0f32])
             .show(ui, |ui| {
@@ -298,41 +1362,391 @@ impl ChessBoard {
                         column -= 1;
                         let idx = row * 8 + column as u8;
                         let curr_square = Square::new(idx as u32);
-                        self.draw_square(curr_square, ctx, ui)
+                        self.draw_square(curr_square, &position, last_move, ctx, ui)
                     }
                     ui.end_row();
                 }
             });
+        self.paint_animation(ctx, ui);
+        self.paint_suggestion(ui);
+        self.update_drag(ctx);
     }
 
-    pub fn why_game_not_running(&self) -> &'static str {
-        if self.chess.is_insufficient_material() {
-            "Draw due to insufficient material"
-        } else if self.chess.is_stalemate() {
-            match self.chess.turn() {
-                Color::Black => "Black to move and is stalemated",
-                Color::White => "White to move and is stalemated",
+    /// Paint the pieces of the last move sliding over the board.
+    /// Repaints are only requested while an animation is running.
+    fn paint_animation(&mut self, ctx: &egui::Context, ui: &Ui) {
+        let Some(animation) = &mut self.animation else {
+            return;
+        };
+        let now = ctx.input(|i| i.time);
+        let start_time = *animation.start_time.get_or_insert(now);
+        let t = ((now - start_time) / self.animation_duration) as f32;
+        if t >= 1.0 || !t.is_finite() {
+            self.animation = None;
+            ctx.request_repaint();
+            return;
+        }
+
+        let square_rects = &self.square_rects;
+        let rect_of = |square: Square| {
+            square_rects
+                .iter()
+                .find(|(s, _)| *s == square)
+                .map(|(_, rect)| *rect)
+        };
+        for (piece, square) in &animation.vanishing {
+            if let Some(rect) = rect_of(*square) {
+                load_image_for_piece(ctx, Some(*piece), None)
+                    .tint(Color32::from_white_alpha(((1.0 - t) * 255.0) as u8))
+                    .paint_at(ui, rect);
             }
-        } else if self.chess.is_checkmate() {
-            match self.chess.turn() {
-                Color::Black => "Black to move and is in checkmate",
-                Color::White => "White to move and is in checkmate",
+        }
+        let t = ease(t);
+        for sliding in &animation.sliding {
+            if let (Some(from), Some(to)) = (rect_of(sliding.from), rect_of(sliding.to)) {
+                let rect = from.translate((to.min - from.min) * t);
+                load_image_for_piece(ctx, Some(sliding.piece), None).paint_at(ui, rect);
             }
-        } else if self.chess.is_game_over() {
-            match self.chess.turn() {
-                Color::Black => "Black to move, but game is over",
-                Color::White => "White to move, but game is over",
+        }
+        ctx.request_repaint();
+    }
+
+    /// Draw the engine's suggested move as an arrow, in analysis mode.
+    fn paint_suggestion(&self, ui: &Ui) {
+        let GameMode::Analysis(analysis) = &self.game_mode else {
+            return;
+        };
+        let Some(suggestion) = &analysis.suggestion else {
+            return;
+        };
+        let squares = LastMove::new(&suggestion.m, self.displayed_position().turn());
+        let rect_of = |square: Square| {
+            self.square_rects
+                .iter()
+                .find(|(s, _)| *s == square)
+                .map(|(_, rect)| *rect)
+        };
+        if let (Some(from), Some(to)) = (rect_of(squares.a), rect_of(squares.b)) {
+            let stroke = Stroke::new(
+                from.width() * 0.15,
+                Color32::from_rgba_unmultiplied(0, 160, 80, 180),
+            );
+            ui.painter()
+                .arrow(from.center(), to.center() - from.center(), stroke);
+        }
+    }
+
+    /// Draw the dragged piece under the pointer, and make the move once it is dropped.
+    fn update_drag(&mut self, ctx: &egui::Context) {
+        let Some(drag) = self.drag else {
+            return;
+        };
+        let (pointer_pos, pointer_down, is_dragging) = ctx.input(|i| {
+            (
+                i.pointer.interact_pos(),
+                i.pointer.primary_down(),
+                i.pointer.is_decidedly_dragging(),
+            )
+        });
+
+        if pointer_down {
+            if let (Some(pos), true) = (pointer_pos, is_dragging) {
+                let size = square_size(ctx);
+                egui::Area::new("dragged_piece")
+                    .order(egui::Order::Tooltip)
+                    .interactable(false)
+                    .fixed_pos(pos - egui::vec2(size, size) / 2f32)
+                    .show(ctx, |ui| {
+                        ui.add(load_image_for_piece(ctx, Some(drag.piece), None));
+                    });
+            }
+            return;
+        }
+
+        // The piece was dropped. Anywhere but a legal target square, it snaps back.
+        self.drag = None;
+        let target = pointer_pos.and_then(|pos| {
+            self.square_rects
+                .iter()
+                .find(|(_, rect)| rect.contains(pos))
+                .map(|(square, _)| *square)
+        });
+        if self.can_premove() {
+            if let Some(target) = target.filter(|t| *t != drag.from) {
+                self.queue_premove(target);
+            }
+            return;
+        }
+        let m = target.and_then(|target| {
+            self.selection.as_ref().and_then(|s| {
+                s.legal_moves
+                    .iter()
+                    .find(|(square, _)| *square == target)
+                    .map(|(_, m)| m.clone())
+            })
+        });
+        if let Some(m) = m {
+            self.play_selected_move(m);
+            // The piece is already where it was dropped
+            self.animation = None;
+        }
+    }
+
+    /// The player may queue premoves while the AI is to move.
+    fn can_premove(&self) -> bool {
+        self.game_is_going
+            && matches!(self.game_mode, GameMode::PlayAgainsAI(_))
+            && self.chess.turn() != self.player_color
+    }
+
+    /// Select a piece of the player's for a premove, counting the premoves already queued.
+    fn select_for_premove(&mut self, square: Square) -> Option<Piece> {
+        let piece = board_after(self.chess.board(), &self.premoves)
+            .piece_at(square)
+            .filter(|p| p.color == self.player_color);
+        self.premove_selection = piece.map(|piece| PremoveSelection::new(piece, square));
+        piece
+    }
+
+    /// Queue a premove of the selected piece, if it can go to `target`.
+    fn queue_premove(&mut self, target: Square) {
+        if let Some(selection) = self.premove_selection.take() {
+            if selection.targets.contains(target) {
+                self.premoves.push(Premove {
+                    from: selection.from,
+                    to: target,
+                });
+            }
+        }
+    }
+
+    /// Play the next premove now that it is the player's turn,
+    /// or discard all of them if it is not legal anymore.
+    fn play_premove(&mut self) {
+        if self.premoves.is_empty() || !self.game_is_going {
+            return;
+        }
+        let premove = self.premoves.remove(0);
+        let m = self
+            .chess
+            .legal_moves()
+            .into_iter()
+            .find(|m| premove.matches(m));
+        match m {
+            Some(m) => self.play_move(&m, MoveAuthor::Player, None),
+            None => {
+                log::info!("Premove {premove:?} is illegal, discarding premoves");
+                self.premoves.clear();
+            }
+        }
+    }
+
+    /// Only the side to move can be selected, and against the AI only the player's pieces.
+    fn can_select(&self, piece: Piece) -> bool {
+        self.chess.turn() == piece.color && self.is_human(piece.color)
+    }
+
+    /// Play a move of the selected piece, asking for the piece to promote to first if needed.
+    fn play_selected_move(&mut self, m: Move) {
+        if m.is_promotion() {
+            self.promotion.show_promotion_choice = true;
+            self.promotion.color = Some(self.chess.turn());
+            self.promotion.promotion_move = Some(m);
+        } else {
+            self.play_move(&m, MoveAuthor::Player, None);
+        }
+    }
+
+    /// The player can make a move on the live board right now.
+    fn can_enter_move(&self) -> bool {
+        self.game_is_going
+            && self.viewed_ply.is_none()
+            && !self.promotion.show_promotion_choice
+            && self.is_human(self.chess.turn())
+    }
+
+    /// Read a typed move in SAN or UCI, and check that it can be played in the live position.
+    fn parse_typed_move(&self, text: &str) -> Result<Move, String> {
+        let text = text.trim();
+        let san_error = match SanPlus::from_ascii(text.as_bytes()) {
+            Ok(san_plus) => match san_plus.san.to_move(&self.chess) {
+                Ok(m) => return Ok(m),
+                Err(SanError::AmbiguousSan) => {
+                    let candidates: Vec<String> = self
+                        .chess
+                        .legal_moves()
+                        .iter()
+                        .filter(|m| san_plus.san.matches(m))
+                        .map(|m| San::from_move(&self.chess, m).to_string())
+                        .collect();
+                    return Err(format!(
+                        "{text} is ambiguous, it could be {}",
+                        candidates.join(" or ")
+                    ));
+                }
+                Err(SanError::IllegalSan) => Some(format!("{text} is not a legal move")),
+            },
+            Err(_) => None,
+        };
+        // Coordinates like "e2e4" may also read as SAN, so UCI is tried before giving up
+        match Uci::from_ascii(text.as_bytes()) {
+            Ok(uci) => uci
+                .to_move(&self.chess)
+                .map_err(|_| format!("{text} is not a legal move")),
+            Err(_) => Err(san_error.unwrap_or_else(|| {
+                format!("{text} is not a move, try e.g. Nf3, e8=Q, O-O or g1f3")
+            })),
+        }
+    }
+
+    /// Text box for playing by typing moves, for keyboard users.
+    pub fn show_move_input(&mut self, ui: &mut Ui) {
+        let enabled = self.can_enter_move();
+        let resp = ui
+            .add_enabled(
+                enabled,
+                egui::TextEdit::singleline(&mut self.move_input)
+                    .hint_text("Type a move, e.g. Nf3")
+                    .code_editor(),
+            )
+            .on_hover_text("SAN like Nf3, e8=Q or O-O, or UCI like g1f3");
+        if resp.changed() {
+            self.move_input_error = None;
+        }
+        if resp.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+            if self.move_input.trim().is_empty() {
+                return;
+            }
+            match self.parse_typed_move(&self.move_input) {
+                Ok(m) => {
+                    self.play_move(&m, MoveAuthor::Player, None);
+                    self.move_input.clear();
+                    self.move_input_error = None;
+                    // Stay in the text box for the next move
+                    resp.request_focus();
+                }
+                Err(err) => self.move_input_error = Some(err),
             }
+        }
+        if let Some(err) = &self.move_input_error {
+            ui.colored_label(ui.visuals().error_fg_color, err);
+        }
+    }
+
+    /// Numbered two-column list of the moves played so far.
+    /// Clicking a move shows the position after it.
+    pub fn show_move_list(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(self.can_undo(), egui::Button::new("⟲ Undo"))
+                .clicked()
+            {
+                self.undo();
+            }
+            if ui
+                .add_enabled(self.can_redo(), egui::Button::new("⟳ Redo"))
+                .clicked()
+            {
+                self.redo();
+            }
+        });
+        ui.horizontal(|ui| {
+            let current = self.viewed_ply.unwrap_or(self.history.len());
+            if ui
+                .add_enabled(current > 0, egui::Button::new("⏮"))
+                .clicked()
+            {
+                self.view_ply(0);
+            }
+            if ui
+                .add_enabled(current > 0, egui::Button::new("⏴"))
+                .clicked()
+            {
+                self.view_ply(current - 1);
+            }
+            if ui
+                .add_enabled(current < self.history.len(), egui::Button::new("⏵"))
+                .clicked()
+            {
+                self.view_ply(current + 1);
+            }
+            if ui
+                .add_enabled(current < self.history.len(), egui::Button::new("⏭"))
+                .clicked()
+            {
+                self.view_ply(self.history.len());
+            }
+        });
+
+        let mut clicked_ply = None;
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                egui::Grid::new("move_list")
+                    .num_columns(3)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        let mut move_number = self.start_position.fullmoves().get();
+                        if self.start_position.turn() == Color::Black && !self.history.is_empty() {
+                            ui.label(format!("{move_number}."));
+                            ui.label("…");
+                        }
+                        for (idx, entry) in self.history.iter().enumerate() {
+                            if entry.color == Color::White {
+                                ui.label(format!("{move_number}."));
+                            }
+                            let is_viewed = self.viewed_ply == Some(idx + 1)
+                                || (self.viewed_ply.is_none() && idx + 1 == self.history.len());
+                            let label = match entry.author {
+                                MoveAuthor::Player | MoveAuthor::Imported => {
+                                    RichText::new(entry.san.to_string())
+                                }
+                                MoveAuthor::Ai => RichText::new(entry.san.to_string()).italics(),
+                            };
+                            if ui
+                                .selectable_label(is_viewed, label)
+                                .on_hover_text(entry.fen.to_string())
+                                .clicked()
+                            {
+                                clicked_ply = Some(idx + 1);
+                            }
+                            if entry.color == Color::Black {
+                                ui.end_row();
+                                move_number += 1;
+                            }
+                        }
+                    });
+            });
+        if let Some(ply) = clicked_ply {
+            self.view_ply(ply);
+        }
+    }
+
+    pub fn why_game_not_running(&self) -> &'static str {
+        if self.viewed_ply.is_some() {
+            "Viewing an earlier position, go to the last move to continue playing."
+        } else if self.ai_move_error.is_some() {
+            "The game is paused because the AI's move could not be fetched."
+        } else if let Some(termination) = self.get_termination() {
+            termination.description()
         } else {
             "The game has not been started yet, please check the menu."
         }
     }
 
-    fn draw_square(&mut self, square: Square, ctx: &egui::Context, ui: &mut egui::Ui) {
+    fn draw_square(
+        &mut self,
+        square: Square,
+        position: &Chess,
+        last_move: Option<LastMove>,
+        ctx: &egui::Context,
+        ui: &mut egui::Ui,
+    ) {
         // Figure out the color of the current square
         let square_color = {
-            let mut color = if Some(square) == self.last_move.as_ref().map(|s| s.a)
-                || Some(square) == self.last_move.as_ref().map(|s| s.b)
+            let mut color = if Some(square) == last_move.map(|s| s.a)
+                || Some(square) == last_move.map(|s| s.b)
             {
                 SquareColor::LAST_MOVE
             } else if square.is_dark() {
@@ -358,11 +1772,25 @@ impl ChessBoard {
                     }
                 }
             }
+            if self
+                .premoves
+                .iter()
+                .any(|p| p.from == square || p.to == square)
+            {
+                color = SquareColor::PREMOVE
+            }
+            if let Some(selection) = &self.premove_selection {
+                if square == selection.from {
+                    color = SquareColor::SELECTED
+                } else if selection.targets.contains(square) {
+                    color = SquareColor::MOVE_TARGET
+                }
+            }
             color
         };
 
-        let piece = self.chess.board().piece_at(square);
-        let who_is_checkmated = self.chess.is_checkmate().then_some(self.chess.turn());
+        let piece = position.board().piece_at(square);
+        let who_is_checkmated = position.is_checkmate().then_some(position.turn());
         if who_is_checkmated.is_some() {
             self.game_is_going = false;
         }
@@ -375,10 +1803,9 @@ impl ChessBoard {
             } = p
             {
                 // and it is in check
-                if self
-                    .chess
+                if position
                     .board()
-                    .attacks_to(square, color.other(), self.chess.board().occupied())
+                    .attacks_to(square, color.other(), position.board().occupied())
                     .any()
                 {
                     // Then tint it
@@ -387,7 +1814,7 @@ impl ChessBoard {
                     // king but not in check
                     Color32::WHITE
                 }
-            } else if self.chess.checkers().contains(square) {
+            } else if position.checkers().contains(square) {
                 // piece is not a king, but is a checker of the king
                 PieceTint::CHECKER
             } else {
@@ -398,8 +1825,18 @@ impl ChessBoard {
             // no piece here
             Color32::WHITE
         };
+        // The dragged piece is drawn under the pointer instead, an arriving one while it slides
+        let is_dragged_away = self.drag.is_some_and(|d| d.from == square)
+            && ctx.input(|i| i.pointer.is_decidedly_dragging());
+        let is_animated =
+            self.viewed_ply.is_none() && self.animation.as_ref().is_some_and(|a| a.hides(square));
+        let shown_piece = if is_dragged_away || is_animated {
+            None
+        } else {
+            piece
+        };
         let img = ImageButton::new(
-            load_image_for_piece(ctx, piece, who_is_checkmated)
+            load_image_for_piece(ctx, shown_piece, who_is_checkmated)
                 .tint(check_tint)
                 .bg_fill(square_color),
         )
@@ -411,25 +1848,57 @@ impl ChessBoard {
             .and_then(|s| s.legal_moves.iter().position(|m| m.0 == square));
 
         // Perform actions based on the input
-        if ui
+        let interactable = self.game_is_going && self.viewed_ply.is_none();
+        let resp = ui
             .add_enabled(
-                self.game_is_going && !self.promotion.show_promotion_choice,
+                interactable && !self.promotion.show_promotion_choice,
                 img.sense(egui::Sense {
-                    click: self.game_is_going,
-                    drag: false,
-                    focusable: self.game_is_going,
+                    click: interactable,
+                    drag: interactable,
+                    focusable: interactable,
                 }),
             )
-            .clone()
-            .on_disabled_hover_text(self.why_game_not_running())
-            .clicked()
-            && !self.promotion.show_promotion_choice
-        {
+            .on_disabled_hover_text(self.why_game_not_running());
+        self.square_rects.push((square, resp.rect));
+
+        if self.can_premove() {
+            let is_premove_target = self
+                .premove_selection
+                .as_ref()
+                .is_some_and(|s| s.targets.contains(square));
+            if resp.secondary_clicked() {
+                self.premoves.clear();
+                self.premove_selection = None;
+            } else if resp.drag_started() && !is_premove_target {
+                if let Some(piece) = self.select_for_premove(square) {
+                    self.drag = Some(DragState {
+                        from: square,
+                        piece,
+                    });
+                }
+            } else if resp.clicked() {
+                if is_premove_target {
+                    self.queue_premove(square);
+                } else {
+                    self.select_for_premove(square);
+                }
+            }
+            return;
+        }
+
+        if resp.drag_started() && !self.promotion.show_promotion_choice {
+            if let Some(piece) = piece.filter(|p| self.can_select(*p)) {
+                // Picking up own piece selects it, so the legal targets are highlighted
+                self.selection = Some(PieceSelection::new(piece, square, &self.chess));
+                self.drag = Some(DragState {
+                    from: square,
+                    piece,
+                });
+            }
+        }
+        if resp.clicked() && !self.promotion.show_promotion_choice {
             if let Some(piece) = piece {
-                if self.chess.turn() == piece.color
-                    && (self.player_color == piece.color
-                        || self.game_mode == GameMode::PlayAgainsYourself)
-                {
+                if self.can_select(piece) {
                     // Selecting own piece
                     self.selection = Some(PieceSelection::new(piece, square, &self.chess));
                     return;
@@ -437,13 +1906,7 @@ impl ChessBoard {
             }
             if let Some(idx) = can_be_moved_to_square {
                 let m = self.selection.as_ref().unwrap().legal_moves[idx].1.clone();
-                if m.is_promotion() {
-                    self.promotion.show_promotion_choice = true;
-                    self.promotion.color = Some(self.selection.as_ref().unwrap().piece.color);
-                    self.promotion.promotion_move = Some(m);
-                } else {
-                    self.play_move(&m);
-                }
+                self.play_selected_move(m);
             } else {
                 self.selection = None;
             }
@@ -485,7 +1948,7 @@ impl ChessBoard {
                                     to: m.to(),
                                     promotion: Some(role),
                                 };
-                                self.play_move(&m);
+                                self.play_move(&m, MoveAuthor::Player, None);
                             }
                         }
                     }
@@ -493,3 +1956,19 @@ impl ChessBoard {
             });
     }
 }
+
+/// Read the move an engine replied with, which must be legal SAN in `position`.
+fn parse_engine_move(position: &Chess, text: &str) -> Result<Move, String> {
+    let san_result = SanPlus::from_ascii(text.as_bytes())
+        .map_err(|e| e.to_string())
+        .and_then(|san_plus| san_plus.san.to_move(position).map_err(|e| e.to_string()));
+    match san_result {
+        Ok(m) => Ok(m),
+        Err(_) if Uci::from_ascii(text.as_bytes()).is_ok() => Err(format!(
+            "The engine replied with {text:?} in UCI notation, but SAN is expected"
+        )),
+        Err(e) => Err(format!(
+            "The engine replied with {text:?}, which cannot be played: {e}"
+        )),
+    }
+}
@@ -0,0 +1,48 @@
+use shakmaty::{zobrist::Zobrist64, Move};
+use web_types::GameMoveResponse;
+
+use super::AiGameSettings;
+use crate::requests::PendingRequest;
+
+/// The move the engine would play in the analysed position.
+pub(crate) struct Suggestion {
+    pub m: Move,
+    pub san: String,
+    pub response: GameMoveResponse,
+}
+
+/// The user moves both sides, and the engine is asked
+/// for its move in every position shown on the board.
+pub(crate) struct Analysis {
+    pub settings: AiGameSettings,
+    /// Counts the positions analysed, a reply for an earlier one is discarded
+    pub generation: u64,
+    /// Hash and ply of the position the current generation is about
+    pub position: Option<(Zobrist64, usize)>,
+    /// The outstanding request, with the generation it was sent for
+    pub request: Option<(u64, PendingRequest<GameMoveResponse>)>,
+    pub suggestion: Option<Suggestion>,
+    pub error: Option<String>,
+}
+
+impl Analysis {
+    pub fn new(settings: AiGameSettings) -> Self {
+        Self {
+            settings,
+            generation: 0,
+            position: None,
+            request: None,
+            suggestion: None,
+            error: None,
+        }
+    }
+
+    /// Forget the analysed position, so the next update asks about the one shown.
+    /// Dropping the outstanding request cancels it.
+    pub fn reset(&mut self) {
+        self.position = None;
+        self.request = None;
+        self.suggestion = None;
+        self.error = None;
+    }
+}
@@ -0,0 +1,80 @@
+use shakmaty::{Board, Color, Move, Piece, Square};
+
+/// A piece sliding from one square to another.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct SlidingPiece {
+    pub piece: Piece,
+    pub from: Square,
+    pub to: Square,
+}
+
+/// The pieces moving, and being captured, in the last move.
+#[derive(Clone, Debug)]
+pub(crate) struct MoveAnimation {
+    pub sliding: Vec<SlidingPiece>,
+    /// Captured pieces, fading out where they stood
+    pub vanishing: Vec<(Piece, Square)>,
+    /// Set on the first frame the animation is drawn
+    pub start_time: Option<f64>,
+}
+
+impl MoveAnimation {
+    /// Animation of `m` made by `color`, with `board` being the board before the move.
+    pub fn new(m: &Move, board: &Board, color: Color) -> Self {
+        let mut sliding = Vec::new();
+        let mut vanishing = Vec::new();
+        match *m {
+            Move::Normal { role, from, to, .. } => {
+                sliding.push(SlidingPiece {
+                    piece: Piece { color, role },
+                    from,
+                    to,
+                });
+                if let Some(captured) = board.piece_at(to) {
+                    vanishing.push((captured, to));
+                }
+            }
+            Move::EnPassant { from, to } => {
+                sliding.push(SlidingPiece {
+                    piece: color.pawn(),
+                    from,
+                    to,
+                });
+                // The captured pawn is next to the capturing one, not on the target square
+                let captured = Square::from_coords(to.file(), from.rank());
+                if let Some(pawn) = board.piece_at(captured) {
+                    vanishing.push((pawn, captured));
+                }
+            }
+            Move::Castle { king, rook } => {
+                let side = m.castling_side().unwrap();
+                sliding.push(SlidingPiece {
+                    piece: color.king(),
+                    from: king,
+                    to: side.king_to(color),
+                });
+                sliding.push(SlidingPiece {
+                    piece: color.rook(),
+                    from: rook,
+                    to: side.rook_to(color),
+                });
+            }
+            Move::Put { .. } => {}
+        }
+        Self {
+            sliding,
+            vanishing,
+            start_time: None,
+        }
+    }
+
+    /// Whether a piece is arriving on `square`, so the board should not draw it there yet.
+    pub fn hides(&self, square: Square) -> bool {
+        self.sliding.iter().any(|s| s.to == square)
+    }
+}
+
+/// Starts and ends slowly, which is easier to follow than a constant speed.
+pub(crate) fn ease(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
@@ -0,0 +1,161 @@
+use std::{fmt::Display, time::Duration};
+
+use shakmaty::Color;
+
+use crate::requests::ClockInfo;
+
+/// Base time plus increment, with an optional delay.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct TimeControl {
+    pub base: Duration,
+    /// Added to a side's clock after each of its moves
+    pub increment: Duration,
+    /// Time at the start of each move that is not charged to the clock
+    pub delay: Duration,
+}
+
+impl TimeControl {
+    const fn new(minutes: u64, increment_seconds: u64) -> Self {
+        Self {
+            base: Duration::from_secs(minutes * 60),
+            increment: Duration::from_secs(increment_seconds),
+            delay: Duration::ZERO,
+        }
+    }
+
+    pub const PRESETS: [TimeControl; 9] = [
+        TimeControl::new(1, 0),
+        TimeControl::new(3, 0),
+        TimeControl::new(3, 2),
+        TimeControl::new(5, 0),
+        TimeControl::new(5, 3),
+        TimeControl::new(10, 0),
+        TimeControl::new(10, 5),
+        TimeControl::new(15, 10),
+        TimeControl::new(30, 0),
+    ];
+}
+
+impl Display for TimeControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let base = self.base.as_secs();
+        if base % 60 == 0 {
+            write!(f, "{}", base / 60)?;
+        } else {
+            write!(f, "{}", self.base.as_secs_f64() / 60.0)?;
+        }
+        write!(f, "+{}", self.increment.as_secs())?;
+        if !self.delay.is_zero() {
+            write!(f, " d{}", self.delay.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+/// Clocks for both sides of a game.
+///
+/// Time is measured in seconds as reported by egui,
+/// which also works on the web where `std::time::Instant` does not.
+#[derive(Clone, Debug)]
+pub(crate) struct ChessClock {
+    time_control: TimeControl,
+    white: Duration,
+    black: Duration,
+    /// The side whose clock is running, and when it started running
+    running: Option<(Color, f64)>,
+}
+
+impl ChessClock {
+    pub fn new(time_control: TimeControl) -> Self {
+        Self {
+            time_control,
+            white: time_control.base,
+            black: time_control.base,
+            running: None,
+        }
+    }
+
+    /// Fresh clocks with the same time control, as at the start of a game.
+    pub fn restarted(&self) -> Self {
+        Self::new(self.time_control)
+    }
+
+    /// A copy with no clock running, to return to after moves are taken back or put back.
+    pub fn stopped(&self, now: f64) -> Self {
+        let mut clock = self.clone();
+        clock.stop(now);
+        clock
+    }
+
+    fn stored(&mut self, color: Color) -> &mut Duration {
+        match color {
+            Color::White => &mut self.white,
+            Color::Black => &mut self.black,
+        }
+    }
+
+    /// Time charged for a turn that started at `since`, taking the delay into account.
+    fn charged(&self, since: f64, now: f64) -> Duration {
+        Duration::from_secs_f64((now - since).max(0.0)).saturating_sub(self.time_control.delay)
+    }
+
+    pub fn running_side(&self) -> Option<Color> {
+        self.running.map(|(color, _)| color)
+    }
+
+    pub fn remaining(&self, color: Color, now: f64) -> Duration {
+        let stored = match color {
+            Color::White => self.white,
+            Color::Black => self.black,
+        };
+        match self.running {
+            Some((running, since)) if running == color => {
+                stored.saturating_sub(self.charged(since, now))
+            }
+            _ => stored,
+        }
+    }
+
+    pub fn start(&mut self, color: Color, now: f64) {
+        self.stop(now);
+        self.running = Some((color, now));
+    }
+
+    pub fn stop(&mut self, now: f64) {
+        if let Some((color, since)) = self.running.take() {
+            let charged = self.charged(since, now);
+            let stored = self.stored(color);
+            *stored = stored.saturating_sub(charged);
+        }
+    }
+
+    /// Clock state as sent to the engine.
+    pub fn info(&self, now: f64) -> ClockInfo {
+        ClockInfo {
+            wtime: self.remaining(Color::White, now),
+            btime: self.remaining(Color::Black, now),
+            winc: self.time_control.increment,
+            binc: self.time_control.increment,
+        }
+    }
+
+    /// Charge the side that just moved, give it the increment
+    /// and start the opponent's clock.
+    pub fn switch(&mut self, now: f64) {
+        if let Some(color) = self.running_side() {
+            self.stop(now);
+            *self.stored(color) += self.time_control.increment;
+            self.running = Some((color.other(), now));
+        }
+    }
+}
+
+/// Minutes and seconds, with tenths when time is running low.
+pub(crate) fn format_clock(time: Duration) -> String {
+    let secs = time.as_secs();
+    if time < Duration::from_secs(10) {
+        format!("0:{:02}.{}", secs, time.subsec_millis() / 100)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
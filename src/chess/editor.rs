@@ -0,0 +1,201 @@
+use std::num::NonZeroU32;
+
+use egui::{ImageButton, Ui};
+use shakmaty::{
+    fen::Fen, CastlingMode, Chess, Color, File, FromSetup, Piece, Rank, Role, Setup, Square,
+};
+
+use super::utils::*;
+
+/// Editor for setting up a position to start a game from.
+pub(crate) struct BoardEditor {
+    setup: Setup,
+    /// Piece placed by clicking a square, `None` removes pieces instead
+    brush: Option<Piece>,
+}
+
+impl BoardEditor {
+    /// Start editing from `fen`, or from the standard position if it is not a valid FEN.
+    pub fn from_fen(fen: &str) -> Self {
+        let setup = Fen::from_ascii(fen.trim().as_bytes())
+            .map(Fen::into_setup)
+            .unwrap_or_default();
+        Self {
+            setup,
+            brush: Some(Piece {
+                color: Color::White,
+                role: Role::Pawn,
+            }),
+        }
+    }
+
+    pub fn fen(&self) -> Fen {
+        Fen::from_setup(self.setup.clone())
+    }
+
+    /// The position being edited, if shakmaty considers it legal.
+    pub fn position(&self) -> Result<Chess, String> {
+        Chess::from_setup(self.setup.clone(), CastlingMode::Standard).map_err(|e| e.to_string())
+    }
+
+    pub fn show_board(&mut self, ctx: &egui::Context, ui: &mut Ui) {
+        egui::Grid::new("board_editor")
+            .spacing([0f32, 0f32])
+            .show(ui, |ui| {
+                for rank in (0..8).rev() {
+                    for file in 0..8 {
+                        let square = Square::from_coords(File::new(file), Rank::new(rank));
+                        self.draw_square(square, ctx, ui);
+                    }
+                    ui.end_row();
+                }
+            });
+    }
+
+    fn draw_square(&mut self, square: Square, ctx: &egui::Context, ui: &mut Ui) {
+        let piece = self.setup.board.piece_at(square);
+        let square_color = if self.setup.ep_square == Some(square) {
+            SquareColor::MOVE_TARGET
+        } else if square.is_dark() {
+            SquareColor::DARK
+        } else {
+            SquareColor::LIGHT
+        };
+        let resp = ui.add(
+            ImageButton::new(load_image_for_piece(ctx, piece, None).bg_fill(square_color))
+                .frame(false),
+        );
+
+        if resp.secondary_clicked() {
+            self.setup.board.discard_piece_at(square);
+        } else if resp.clicked() {
+            match self.brush {
+                // Clicking with the same piece again removes it
+                Some(brush) if piece != Some(brush) => self.setup.board.set_piece_at(square, brush),
+                _ => self.setup.board.discard_piece_at(square),
+            }
+        }
+    }
+
+    pub fn show_controls(&mut self, ctx: &egui::Context, ui: &mut Ui) {
+        ui.label("Click to place the selected piece, right click to remove.");
+        for color in [Color::White, Color::Black] {
+            ui.horizontal(|ui| {
+                ui.spacing_mut().item_spacing = [1f32, 0f32].into();
+                for role in Role::ALL {
+                    let piece = Piece { color, role };
+                    if ui
+                        .add(
+                            ImageButton::new(
+                                load_image_for_piece(ctx, Some(piece), None)
+                                    .fit_to_exact_size([32f32, 32f32].into()),
+                            )
+                            .selected(self.brush == Some(piece)),
+                        )
+                        .clicked()
+                    {
+                        self.brush = Some(piece);
+                    }
+                }
+            });
+        }
+        ui.horizontal(|ui| {
+            if ui
+                .selectable_label(self.brush.is_none(), "Eraser")
+                .clicked()
+            {
+                self.brush = None;
+            }
+            if ui.button("Clear board").clicked() {
+                self.setup = Setup::empty();
+            }
+            if ui.button("Starting position").clicked() {
+                self.setup = Setup::default();
+            }
+        });
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("Side to move");
+            let turn = self.setup.turn;
+            ui.radio_value(&mut self.setup.turn, Color::White, "White");
+            ui.radio_value(&mut self.setup.turn, Color::Black, "Black");
+            if self.setup.turn != turn {
+                // The en passant square is always behind the pawn that just moved
+                self.setup.ep_square = self
+                    .setup
+                    .ep_square
+                    .map(|sq| Square::from_coords(sq.file(), ep_rank(self.setup.turn)));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Castling");
+            for (rook, label) in [
+                (Square::H1, "O-O"),
+                (Square::A1, "O-O-O"),
+                (Square::H8, "... O-O"),
+                (Square::A8, "... O-O-O"),
+            ] {
+                let mut allowed = self.setup.castling_rights.contains(rook);
+                if ui.checkbox(&mut allowed, label).changed() {
+                    self.setup.castling_rights.set(rook, allowed);
+                }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("En passant");
+            let selected_text = self
+                .setup
+                .ep_square
+                .map_or_else(|| "None".to_string(), |sq| sq.to_string());
+            egui::ComboBox::from_id_source("editor_ep_square")
+                .selected_text(selected_text)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.setup.ep_square, None, "None");
+                    for file in File::ALL {
+                        let square = Square::from_coords(file, ep_rank(self.setup.turn));
+                        ui.selectable_value(
+                            &mut self.setup.ep_square,
+                            Some(square),
+                            square.to_string(),
+                        );
+                    }
+                });
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Halfmove clock");
+            ui.add(egui::DragValue::new(&mut self.setup.halfmoves).clamp_range(0..=150));
+            ui.label("Move number");
+            let mut fullmoves = self.setup.fullmoves.get();
+            if ui
+                .add(egui::DragValue::new(&mut fullmoves).clamp_range(1..=999))
+                .changed()
+            {
+                self.setup.fullmoves = NonZeroU32::new(fullmoves).unwrap_or(NonZeroU32::MIN);
+            }
+        });
+
+        ui.separator();
+        let fen = self.fen().to_string();
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut fen.as_str()).code_editor());
+            if ui.button("Copy").clicked() {
+                ui.output_mut(|o| o.copied_text = fen.clone());
+            }
+        });
+        if let Err(err) = self.position() {
+            ui.colored_label(ui.visuals().error_fg_color, err);
+        }
+    }
+}
+
+/// Rank of the en passant square when `turn` is to move.
+fn ep_rank(turn: Color) -> Rank {
+    match turn {
+        Color::White => Rank::Sixth,
+        Color::Black => Rank::Third,
+    }
+}
@@ -0,0 +1,75 @@
+use shakmaty::{
+    fen::Fen,
+    san::SanPlus,
+    zobrist::{Zobrist64, ZobristHash},
+    Chess, Color, EnPassantMode, Move, Square,
+};
+use web_types::GameMoveResponse;
+
+use super::clock::ChessClock;
+
+/// Who made a move recorded in the game history.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum MoveAuthor {
+    /// Made on this board by a human
+    Player,
+    /// Received from the backend engine
+    Ai,
+    /// Read from a PGN
+    Imported,
+}
+
+/// The two squares highlighted after a move.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct LastMove {
+    pub a: Square,
+    pub b: Square,
+}
+
+impl LastMove {
+    /// `color` is the side that made the move `m`.
+    pub fn new(m: &Move, color: Color) -> Self {
+        if let Move::Castle { king, .. } = m {
+            LastMove {
+                a: *king,
+                b: m.castling_side().unwrap().king_to(color),
+            }
+        } else {
+            LastMove {
+                a: m.from().unwrap(),
+                b: m.to(),
+            }
+        }
+    }
+}
+
+/// A single played move along with the position it resulted in.
+#[derive(Clone, Debug)]
+pub(crate) struct HistoryEntry {
+    pub m: Move,
+    pub san: SanPlus,
+    /// Position after the move has been played
+    pub position: Chess,
+    pub fen: Fen,
+    /// Hash of `position`, for detecting repetitions
+    pub hash: Zobrist64,
+    /// The side that made the move
+    pub color: Color,
+    pub author: MoveAuthor,
+    /// Engine metadata, only present for moves made by the AI
+    pub ai_response: Option<GameMoveResponse>,
+    /// Clocks right after the move, stopped, so undo and redo can return to them
+    pub clock: Option<ChessClock>,
+}
+
+impl HistoryEntry {
+    pub fn last_move(&self) -> LastMove {
+        LastMove::new(&self.m, self.color)
+    }
+}
+
+/// Hash identifying a position for the repetition rules:
+/// same pieces, side to move, castling rights and en passant possibilities.
+pub(crate) fn position_hash(position: &Chess) -> Zobrist64 {
+    position.zobrist_hash(EnPassantMode::Legal)
+}
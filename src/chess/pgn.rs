@@ -0,0 +1,241 @@
+use anyhow::{anyhow, bail, Result};
+use pgn_reader::{BufferedReader, RawHeader, Skip, Visitor};
+use serde::{Deserialize, Serialize};
+use shakmaty::{fen::Fen, san::SanPlus, CastlingMode, Chess, Color, EnPassantMode, Move, Position};
+
+use super::ChessBoard;
+
+/// PGN recommends keeping movetext lines below 80 characters.
+const MAX_LINE_LENGTH: usize = 79;
+
+/// Who played a game and when, as PGN tags. Unknown values are `?`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct GameTags {
+    pub white: String,
+    pub black: String,
+    /// As `YYYY.MM.DD`
+    pub date: String,
+}
+
+impl Default for GameTags {
+    fn default() -> Self {
+        Self {
+            white: "?".to_string(),
+            black: "?".to_string(),
+            date: "????.??.??".to_string(),
+        }
+    }
+}
+
+/// Today's date in UTC, formatted for the `Date` tag.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn today() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    format!("{year:04}.{month:02}.{day:02}")
+}
+
+/// Today's date in UTC, formatted for the `Date` tag.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn today() -> String {
+    let date = js_sys::Date::new_0();
+    format!(
+        "{:04}.{:02}.{:02}",
+        date.get_utc_full_year(),
+        date.get_utc_month() + 1,
+        date.get_utc_date()
+    )
+}
+
+/// Year, month and day of the day `days` after 1970-01-01, in the proleptic Gregorian calendar.
+#[cfg(not(target_arch = "wasm32"))]
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // Counted from 0000-03-01, so leap days come last in each year
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+impl ChessBoard {
+    /// Export the current game, finished or not, as PGN,
+    /// with the players and date recorded when it started.
+    pub fn to_pgn(&self) -> String {
+        self.to_pgn_for_event("Casual game", "-")
+    }
+
+    /// Like [`Self::to_pgn`], for a game played as round `round` of `event`.
+    pub fn to_pgn_for_event(&self, event: &str, round: &str) -> String {
+        let result = self
+            .get_termination()
+            .map_or_else(|| "*".to_string(), |t| t.outcome().to_string());
+
+        // Seven Tag Roster
+        let mut pgn = String::new();
+        for (tag, value) in [
+            ("Event", event),
+            ("Site", "Unchessful Games"),
+            ("Date", self.tags.date.as_str()),
+            ("Round", round),
+            ("White", self.tags.white.as_str()),
+            ("Black", self.tags.black.as_str()),
+            ("Result", result.as_str()),
+        ] {
+            pgn += &format_tag(tag, value);
+        }
+        let start_fen = Fen::from_position(self.start_position.clone(), EnPassantMode::Legal);
+        if start_fen.to_string()
+            != Fen::from_position(Chess::default(), EnPassantMode::Legal).to_string()
+        {
+            pgn += &format_tag("SetUp", "1");
+            pgn += &format_tag("FEN", &start_fen.to_string());
+        }
+        pgn.push('\n');
+
+        let mut tokens = Vec::new();
+        let mut move_number = self.start_position.fullmoves().get();
+        // Black's moves need their own number at the start and after a comment.
+        let mut needs_number = true;
+        for entry in &self.history {
+            match entry.color {
+                Color::White => tokens.push(format!("{move_number}.")),
+                Color::Black if needs_number => tokens.push(format!("{move_number}...")),
+                Color::Black => {}
+            }
+            tokens.push(entry.san.to_string());
+            needs_number = false;
+
+            if let Some(resp) = &entry.ai_response {
+                tokens.push(format!(
+                    "{{{}; time: {:?}}}",
+                    resp.status_text.replace('}', ")"),
+                    resp.move_timing
+                ));
+                needs_number = true;
+            }
+            if entry.color == Color::Black {
+                move_number += 1;
+            }
+        }
+        tokens.push(result);
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > MAX_LINE_LENGTH {
+                pgn += &line;
+                pgn.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line += &token;
+        }
+        pgn += &line;
+        pgn.push('\n');
+        pgn
+    }
+}
+
+fn format_tag(tag: &str, value: &str) -> String {
+    let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("[{tag} \"{value}\"]\n")
+}
+
+/// A game read from PGN, with every move checked to be legal.
+pub(crate) struct ImportedGame {
+    pub start_position: Chess,
+    pub moves: Vec<Move>,
+    pub tags: GameTags,
+}
+
+/// Collects the mainline of the first game in a PGN.
+#[derive(Default)]
+struct GameCollector {
+    fen: Option<String>,
+    variant: Option<String>,
+    tags: GameTags,
+    sans: Vec<SanPlus>,
+}
+
+impl Visitor for GameCollector {
+    type Result = ();
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        let value = value.decode_utf8_lossy().into_owned();
+        match key {
+            b"FEN" => self.fen = Some(value),
+            b"Variant" => self.variant = Some(value),
+            b"White" => self.tags.white = value,
+            b"Black" => self.tags.black = value,
+            b"Date" => self.tags.date = value,
+            _ => {}
+        }
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        // Only the mainline is imported
+        Skip(true)
+    }
+
+    fn san(&mut self, san_plus: SanPlus) {
+        self.sans.push(san_plus);
+    }
+
+    fn end_game(&mut self) -> Self::Result {}
+}
+
+/// Read the first game from `text`.
+/// Unsupported variants, bad starting positions and illegal moves are reported as errors.
+pub(crate) fn parse_pgn(text: &str) -> Result<ImportedGame> {
+    let mut collector = GameCollector::default();
+    let mut reader = BufferedReader::new_cursor(text.as_bytes());
+    if reader.read_game(&mut collector)?.is_none() {
+        bail!("No game found");
+    }
+
+    if let Some(variant) = &collector.variant {
+        let supported = ["standard", "chess", "normal", "from position"];
+        if !supported.contains(&variant.to_lowercase().as_str()) {
+            bail!("Unsupported variant: {variant}");
+        }
+    }
+
+    let start_position: Chess = match &collector.fen {
+        Some(fen) => Fen::from_ascii(fen.as_bytes())
+            .map_err(|e| anyhow!("Invalid FEN tag \"{fen}\": {e}"))?
+            .into_position(CastlingMode::Standard)
+            .map_err(|e| anyhow!("Invalid starting position \"{fen}\": {e}"))?,
+        None => Chess::default(),
+    };
+
+    let mut position = start_position.clone();
+    let mut moves = Vec::with_capacity(collector.sans.len());
+    for san_plus in collector.sans {
+        let number = match position.turn() {
+            Color::White => format!("{}.", position.fullmoves()),
+            Color::Black => format!("{}...", position.fullmoves()),
+        };
+        let m = san_plus
+            .san
+            .to_move(&position)
+            .map_err(|e| anyhow!("Move {number} {san_plus} is not valid: {e}"))?;
+        position.play_unchecked(&m);
+        moves.push(m);
+    }
+
+    Ok(ImportedGame {
+        start_position,
+        moves,
+        tags: collector.tags,
+    })
+}
@@ -0,0 +1,121 @@
+use shakmaty::{
+    attacks, Bitboard, Board, CastlingSide, Color, File, Move, Piece, Rank, Role, Square,
+};
+
+/// A move queued by the player while the engine is thinking.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Premove {
+    pub from: Square,
+    pub to: Square,
+}
+
+impl Premove {
+    /// Whether the legal move `m` is the one that was queued.
+    /// Pawns reaching the last rank always promote to a queen.
+    pub fn matches(&self, m: &Move) -> bool {
+        if m.from() != Some(self.from) {
+            return false;
+        }
+        match m {
+            Move::Castle { king, rook } => {
+                let king_to =
+                    Square::from_coords(m.castling_side().unwrap().king_to_file(), king.rank());
+                self.to == king_to || self.to == *rook
+            }
+            _ => m.to() == self.to && m.promotion().map_or(true, |role| role == Role::Queen),
+        }
+    }
+
+    /// The side `piece` castles to with this premove, if it is a king moving two files.
+    fn castling_side(&self, piece: Piece) -> Option<CastlingSide> {
+        let files = i32::from(self.to.file()) - i32::from(self.from.file());
+        (piece.role == Role::King && self.from.rank() == self.to.rank() && files.abs() == 2)
+            .then(|| CastlingSide::from_king_side(files > 0))
+    }
+}
+
+/// Piece picked up for a premove, with the squares it may be queued to go to.
+pub(crate) struct PremoveSelection {
+    pub from: Square,
+    pub targets: Bitboard,
+}
+
+impl PremoveSelection {
+    pub fn new(piece: Piece, from: Square) -> Self {
+        Self {
+            from,
+            targets: premove_targets(piece, from),
+        }
+    }
+}
+
+/// Squares `piece` could move to from `from` on an empty board.
+/// The position will have changed by the time a premove is played,
+/// so only the way the piece moves is taken into account.
+fn premove_targets(piece: Piece, from: Square) -> Bitboard {
+    match piece.role {
+        Role::Pawn => {
+            let (forward, start_rank) = match piece.color {
+                Color::White => (8, Rank::Second),
+                Color::Black => (-8, Rank::Seventh),
+            };
+            let mut targets = attacks::pawn_attacks(piece.color, from);
+            if let Some(square) = from.offset(forward) {
+                targets.add(square);
+            }
+            if from.rank() == start_rank {
+                if let Some(square) = from.offset(2 * forward) {
+                    targets.add(square);
+                }
+            }
+            targets
+        }
+        Role::Knight => attacks::knight_attacks(from),
+        Role::Bishop => attacks::bishop_attacks(from, Bitboard::EMPTY),
+        Role::Rook => attacks::rook_attacks(from, Bitboard::EMPTY),
+        Role::Queen => attacks::queen_attacks(from, Bitboard::EMPTY),
+        Role::King => {
+            let mut targets = attacks::king_attacks(from);
+            let home = match piece.color {
+                Color::White => Square::E1,
+                Color::Black => Square::E8,
+            };
+            if from == home {
+                // Castling
+                for square in [from.offset(2), from.offset(-2)].into_iter().flatten() {
+                    targets.add(square);
+                }
+            }
+            targets
+        }
+    }
+}
+
+/// The board as it will look once `premoves` have been played,
+/// not knowing what the opponent will reply.
+pub(crate) fn board_after(board: &Board, premoves: &[Premove]) -> Board {
+    let mut board = board.clone();
+    for premove in premoves {
+        if let Some(piece) = board.remove_piece_at(premove.from) {
+            if let Some(side) = premove.castling_side(piece) {
+                // The rook jumps over the king, as in a played castling move
+                let rank = premove.from.rank();
+                let rook_file = match side {
+                    CastlingSide::KingSide => File::H,
+                    CastlingSide::QueenSide => File::A,
+                };
+                let rook_from = Square::from_coords(rook_file, rank);
+                let rook = Piece {
+                    color: piece.color,
+                    role: Role::Rook,
+                };
+                if board.piece_at(rook_from) == Some(rook) {
+                    board.remove_piece_at(rook_from);
+                    board.set_piece_at(Square::from_coords(side.rook_to_file(), rank), rook);
+                }
+            }
+            board.set_piece_at(premove.to, piece);
+        }
+    }
+    board
+}
@@ -108,6 +108,7 @@ impl SquareColor {
     pub const MOVE_TARGET: Color32 = Color32::LIGHT_GREEN;
     pub const ATTACK_TARGET: Color32 = Color32::LIGHT_RED;
     pub const LAST_MOVE: Color32 = Color32::KHAKI;
+    pub const PREMOVE: Color32 = Color32::from_rgb(160, 150, 210);
 }
 
 pub struct PieceTint {}
@@ -0,0 +1,172 @@
+use egui::{Grid, Label};
+use tokio::sync::mpsc;
+use web_types::{EngineDescription, EngineRef, EngineVariant};
+
+use crate::{
+    requests::{self, request_channel, PendingRequest, RequestLoopComm},
+    show_error_with_retry,
+};
+
+/// Choice of an engine and one of its variants, for one side of the board.
+#[derive(Default)]
+pub(crate) struct EnginePicker {
+    pub selected_engine: Option<EngineRef>,
+    pub desc: Option<EngineDescription>,
+    pub variant: Option<EngineVariant>,
+    desc_receiver: Option<PendingRequest<EngineDescription>>,
+    desc_error: Option<String>,
+}
+
+/// Something the game has to react to, reported by [`EnginePicker::show`].
+pub(crate) enum PickerChange {
+    /// The user picked another engine or variant
+    Selection,
+    /// The description of the selected engine arrived
+    Description(EngineDescription),
+}
+
+impl EnginePicker {
+    pub fn fetch_description(&mut self, sender: &mpsc::Sender<RequestLoopComm>) {
+        if let Some(selected_engine) = &self.selected_engine {
+            let (responder, receiver) = request_channel();
+            let req = RequestLoopComm::FetchEngineDescription(selected_engine.clone(), responder);
+            requests::send_request(sender, req);
+            self.desc_receiver = Some(receiver);
+            self.desc_error = None;
+        }
+    }
+
+    /// Name used for this engine in exported games.
+    pub fn player_name(&self) -> String {
+        match (&self.selected_engine, &self.variant) {
+            (Some(engine), Some(variant)) => format!("{} ({})", engine.name, variant.name),
+            (Some(engine), None) => engine.name.clone(),
+            _ => "AI".to_string(),
+        }
+    }
+
+    /// Engine and variant selection, fetching the engine's description when needed.
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        engines: &[EngineRef],
+        sender: &mpsc::Sender<RequestLoopComm>,
+    ) -> Option<PickerChange> {
+        let mut change = None;
+        if let Some(data) = self.selected_engine.as_mut() {
+            let cbox_resp = egui::ComboBox::from_id_source("engine_selection")
+                .selected_text(data.name.to_string())
+                .show_ui(ui, |ui| {
+                    let mut is_clicked = false;
+                    for engine in engines {
+                        if ui
+                            .selectable_value(data, engine.clone(), engine.name.clone())
+                            .clicked()
+                        {
+                            is_clicked = true;
+                        }
+                    }
+                    is_clicked
+                });
+            let selected_engine = data.clone();
+
+            if cbox_resp.inner.is_some_and(|v| v) {
+                log::info!("Engine changed to: {data:?}");
+                change = Some(PickerChange::Selection);
+                self.variant = None;
+                self.desc = None;
+                self.fetch_description(sender);
+            }
+
+            Grid::new("current_engine_info").show(ui, |ui| {
+                ui.label("Name");
+                ui.label(selected_engine.name);
+                ui.end_row();
+                ui.label("Id");
+                ui.label(selected_engine.engine_id);
+                ui.end_row();
+                ui.label("URL");
+                ui.hyperlink(selected_engine.entrypoint_url);
+                ui.end_row();
+            });
+        }
+
+        if self.selected_engine.is_none() {
+            return change;
+        }
+        ui.heading("Select variant");
+        if ui.button("Update info").clicked() {
+            self.fetch_description(sender);
+        }
+        if self.desc.is_none() && self.desc_receiver.is_none() && self.desc_error.is_none() {
+            self.fetch_description(sender);
+        }
+        if let Some(recv) = &self.desc_receiver {
+            match recv.try_recv() {
+                Err(oneshot::TryRecvError::Empty) => {
+                    ui.label("Loading engine description...");
+                    ui.spinner();
+                }
+                Ok(Ok(desc)) => {
+                    log::info!("Received engine description: {desc:?}");
+                    self.desc = Some(desc.clone());
+                    self.variant = None;
+                    self.desc_receiver = None;
+                    change = Some(PickerChange::Description(desc));
+                }
+                Ok(Err(e)) => {
+                    log::error!("Could not fetch the engine description: {e:#}");
+                    self.desc_error =
+                        Some(format!("Could not fetch the engine description: {e:#}"));
+                    self.desc_receiver = None;
+                }
+                Err(oneshot::TryRecvError::Disconnected) => {
+                    self.desc_error =
+                        Some("The request for the engine description was dropped".to_string());
+                    self.desc_receiver = None;
+                }
+            }
+        }
+        if let Some(err) = &self.desc_error {
+            if show_error_with_retry(ui, err) {
+                self.fetch_description(sender);
+            }
+        }
+        if let Some(desc) = &self.desc {
+            ui.heading(desc.name.clone());
+            ui.add(Label::new(desc.text_description.clone()).wrap(true));
+
+            let mut checkpoint = self
+                .variant
+                .get_or_insert_with(|| desc.best_available_variant.clone())
+                .clone();
+            if egui::ComboBox::from_id_source("variant_selection")
+                .selected_text(checkpoint.name.to_string())
+                .show_ui(ui, |ui| {
+                    let mut is_clicked = false;
+                    for variant in &desc.variants {
+                        is_clicked |= ui
+                            .selectable_value(
+                                &mut checkpoint,
+                                variant.clone(),
+                                variant.name.clone(),
+                            )
+                            .clicked();
+                    }
+                    is_clicked
+                })
+                .inner
+                .is_some_and(|v| v)
+            {
+                log::info!("Changed variant: new is {checkpoint:?}");
+                self.variant = Some(checkpoint);
+                change = Some(PickerChange::Selection);
+            }
+
+            if let Some(chk) = &self.variant {
+                ui.label(&chk.description);
+            }
+        }
+        change
+    }
+}
@@ -0,0 +1,75 @@
+use anyhow::Result;
+
+/// Let the user pick a text file and read it.
+/// The receiver gets `None` if the user cancelled the dialog.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn open_text_file(_ctx: &egui::Context) -> oneshot::Receiver<Result<Option<String>>> {
+    let (sender, receiver) = oneshot::channel();
+    let contents = rfd::FileDialog::new()
+        .pick_file()
+        .map(std::fs::read_to_string)
+        .transpose()
+        .map_err(Into::into);
+    let _ = sender.send(contents);
+    receiver
+}
+
+/// Let the user pick a text file and read it.
+/// The receiver gets `None` if the user cancelled the dialog.
+#[cfg(target_arch = "wasm32")]
+pub fn open_text_file(ctx: &egui::Context) -> oneshot::Receiver<Result<Option<String>>> {
+    let (sender, receiver) = oneshot::channel();
+    let ctx = ctx.clone();
+    wasm_bindgen_futures::spawn_local(async move {
+        let contents = match rfd::AsyncFileDialog::new().pick_file().await {
+            Some(file) => String::from_utf8(file.read().await)
+                .map(Some)
+                .map_err(Into::into),
+            None => Ok(None),
+        };
+        let _ = sender.send(contents);
+        ctx.request_repaint();
+    });
+    receiver
+}
+
+/// Let the user save `contents` as a file, suggesting `file_name`.
+/// Natively this opens a save dialog.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_text_file(file_name: &str, contents: &str) -> Result<()> {
+    let Some(path) = rfd::FileDialog::new().set_file_name(file_name).save_file() else {
+        // The user cancelled the dialog
+        return Ok(());
+    };
+    std::fs::write(&path, contents)?;
+    log::info!("Saved {}", path.display());
+    Ok(())
+}
+
+/// Let the user save `contents` as a file, suggesting `file_name`.
+/// On the web this triggers a download.
+#[cfg(target_arch = "wasm32")]
+pub fn save_text_file(file_name: &str, contents: &str) -> Result<()> {
+    use anyhow::anyhow;
+    use wasm_bindgen::JsCast;
+
+    let parts = js_sys::Array::new();
+    parts.push(&wasm_bindgen::JsValue::from_str(contents));
+    let blob = web_sys::Blob::new_with_str_sequence(&parts).map_err(|e| anyhow!("{e:?}"))?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob).map_err(|e| anyhow!("{e:?}"))?;
+
+    let document = web_sys::window()
+        .and_then(|w| w.document())
+        .ok_or_else(|| anyhow!("No document to download from"))?;
+    let anchor: web_sys::HtmlAnchorElement = document
+        .create_element("a")
+        .map_err(|e| anyhow!("{e:?}"))?
+        .dyn_into()
+        .map_err(|e| anyhow!("{e:?}"))?;
+    anchor.set_href(&url);
+    anchor.set_download(file_name);
+    anchor.click();
+
+    web_sys::Url::revoke_object_url(&url).map_err(|e| anyhow!("{e:?}"))?;
+    Ok(())
+}
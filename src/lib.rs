@@ -1,63 +1,272 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
-use std::fmt::Display;
+use std::{fmt::Display, time::Duration};
 
-use chess::{AiGameSettings, GameMode};
-use shakmaty::Color;
+use chess::{
+    AiGameSettings, AiVsAiSettings, Analysis, DrawOfferFallback, GameMode, InvalidMoveHandling,
+    TimeControl,
+};
+use engine_picker::{EnginePicker, PickerChange};
+use match_runner::{MatchEngine, MatchRunner, MatchSettings};
+use shakmaty::{fen::Fen, CastlingMode, Chess, Color};
 use tokio::sync::mpsc;
+use tournament::{
+    ParticipantLoader, Tournament, TournamentFormat, TournamentRunner, TournamentSettings,
+};
 
 use anyhow::Result;
 use egui::{Align2, Button, Grid, Image, ImageButton, Label, RichText, Window};
-use requests::RequestLoopComm;
-use web_types::{EngineDescription, EngineDirectory, EngineRef, EngineVariant};
+use persistence::{SavedGame, SavedState, STATE_KEY};
+use requests::{request_channel, PendingRequest, RequestLoopComm, RequestTimeouts};
+use serde::{Deserialize, Serialize};
+use web_types::{EngineDescription, EngineDirectory};
 
 mod chess;
+mod engine_picker;
+mod files;
+mod match_runner;
+mod persistence;
 mod requests;
+mod tournament;
+
+pub use requests::DEFAULT_API_URL;
+
+/// Storage key under which the engine directory root is persisted.
+const API_URL_KEY: &str = "api_url";
+/// Why a gauntlet cannot start.
+const GAUNTLET_WITHOUT_VARIANT: &str =
+    "Select the variant of the engine playing the gauntlet first";
 
 pub struct App {
     chessboard: chess::ChessBoard,
     game_mode_selection: GameModeSelector,
     fetch_engine_list_first_boot: bool,
-    engine_data: EngineData,
+    available_engines: Option<EngineDirectory>,
+    /// Engine played against, or White in a game between engines
+    engine_picker: EnginePicker,
+    /// Black in a game between engines
+    second_engine_picker: EnginePicker,
     request_loop_sender: mpsc::Sender<requests::RequestLoopComm>,
-    engine_dir_receiver: Option<oneshot::Receiver<Result<EngineDirectory>>>,
-    engine_desc_receiver: Option<oneshot::Receiver<Result<EngineDescription>>>,
+    engine_dir_receiver: Option<PendingRequest<EngineDirectory>>,
+    /// Timeouts the request loop uses, only changed once it accepted new ones
+    request_timeouts: RequestTimeouts,
+    request_timeouts_error: Option<String>,
+    engine_dir_error: Option<String>,
+    api_url: String,
+    api_url_input: String,
+    api_url_error: Option<String>,
+    file_error: Option<String>,
+    pgn_import: Option<PgnImport>,
+    /// FEN to start new games from, the standard position if empty
+    start_fen: String,
+    /// Shown instead of the game while setting up a position
+    board_editor: Option<chess::BoardEditor>,
+    /// Send engines the whole game instead of only the current position
+    send_move_history: bool,
+    dark_mode: bool,
+    /// Saved engine selection, applied once the engine directory has been fetched
+    restore: Option<PendingRestore>,
+    /// Why the saved engine selection could not be restored
+    restore_notice: Option<String>,
+    match_settings: MatchSettings,
+    /// The match being played or last played, shown in its own window
+    match_runner: Option<MatchRunner>,
+    tournament_settings: TournamentSettings,
+    /// Engine descriptions being fetched for a tournament about to start
+    participant_loader: Option<ParticipantLoader>,
+    tournament_runner: Option<TournamentRunner>,
+    /// Why the last tournament could not be started
+    tournament_error: Option<String>,
+}
+
+/// Engine and variant used last time, to be looked up again when they arrive.
+struct PendingRestore {
+    engine_id: String,
+    variant_name: Option<String>,
+    /// Continue the saved game against the engine once the variant is known
+    resume_game: bool,
 }
 
-#[derive(PartialEq, Eq)]
+/// State of the PGN import window.
+#[derive(Default)]
+struct PgnImport {
+    text: String,
+    error: Option<String>,
+    file_receiver: Option<oneshot::Receiver<Result<Option<String>>>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 enum GameModeSelector {
     PlayAgainsAI,
     PlayAgainsYourself,
+    AiVsAi,
+    Analysis,
 }
 impl Display for GameModeSelector {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             GameModeSelector::PlayAgainsAI => write!(f, "Play against AI"),
             GameModeSelector::PlayAgainsYourself => write!(f, "Play against Yourself"),
+            GameModeSelector::AiVsAi => write!(f, "Watch AI vs AI"),
+            GameModeSelector::Analysis => write!(f, "Analyse with AI"),
         }
     }
 }
 
-#[derive(Default)]
-struct EngineData {
-    available_engines: Option<EngineDirectory>,
-    selected_engine: Option<EngineRef>,
-    desc: Option<EngineDescription>,
-    variant: Option<EngineVariant>,
-}
-
 impl App {
-    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
-        let req_comm_loop = requests::run_request_loop(cc.egui_ctx.clone());
+    /// Create the app.
+    ///
+    /// `api_url` overrides the engine directory root (from the command line or the page);
+    /// otherwise the persisted value is used, falling back to [`DEFAULT_API_URL`].
+    pub fn new(cc: &eframe::CreationContext<'_>, api_url: Option<String>) -> Self {
+        let api_url = api_url
+            .or_else(|| cc.storage.and_then(|s| eframe::get_value(s, API_URL_KEY)))
+            .unwrap_or_else(|| DEFAULT_API_URL.to_string());
+        log::info!("Using API URL: {api_url}");
+        let request_timeouts = RequestTimeouts::default();
+        let req_comm_loop =
+            requests::run_request_loop(cc.egui_ctx.clone(), api_url.clone(), request_timeouts);
+        let saved: Option<SavedState> = cc.storage.and_then(|s| eframe::get_value(s, STATE_KEY));
 
-        Self {
+        let mut app = Self {
             chessboard: Default::default(),
             game_mode_selection: GameModeSelector::PlayAgainsAI,
             fetch_engine_list_first_boot: true,
-            engine_data: EngineData::default(),
+            available_engines: None,
+            engine_picker: EnginePicker::default(),
+            second_engine_picker: EnginePicker::default(),
             request_loop_sender: req_comm_loop,
-            engine_desc_receiver: None,
             engine_dir_receiver: None,
+            request_timeouts,
+            request_timeouts_error: None,
+            engine_dir_error: None,
+            api_url_input: api_url.clone(),
+            api_url,
+            api_url_error: None,
+            file_error: None,
+            pgn_import: None,
+            start_fen: String::new(),
+            board_editor: None,
+            send_move_history: true,
+            dark_mode: cc.egui_ctx.style().visuals.dark_mode,
+            restore: None,
+            restore_notice: None,
+            match_settings: MatchSettings::default(),
+            match_runner: None,
+            tournament_settings: TournamentSettings::default(),
+            participant_loader: None,
+            tournament_runner: None,
+            tournament_error: None,
+        };
+        if let Some(saved) = saved {
+            app.restore_state(&cc.egui_ctx, saved);
+        }
+        app
+    }
+
+    /// Apply the state saved by [`eframe::App::save`].
+    /// The engine and variant are only known once they have been fetched again.
+    fn restore_state(&mut self, ctx: &egui::Context, saved: SavedState) {
+        self.game_mode_selection = saved.game_mode;
+        self.chessboard.player_color = if saved.player_is_white {
+            Color::White
+        } else {
+            Color::Black
+        };
+        self.dark_mode = saved.dark_mode;
+        ctx.set_visuals(if saved.dark_mode {
+            egui::Visuals::dark()
+        } else {
+            egui::Visuals::light()
+        });
+        self.start_fen = saved.start_fen;
+        self.tournament_runner = saved.tournament.map(|tournament| {
+            TournamentRunner::new(
+                tournament,
+                true,
+                self.send_move_history,
+                self.request_loop_sender.clone(),
+            )
+        });
+
+        // Finished and stopped games are only shown for replay
+        let mut in_progress = false;
+        if let Some(saved_game) = saved.game {
+            match saved_game.to_game() {
+                Ok(game) => {
+                    self.chessboard.load_game(game);
+                    if let Some(termination) = saved_game.termination {
+                        self.chessboard
+                            .restore_termination(termination.to_termination());
+                    }
+                    in_progress = saved_game.in_progress;
+                }
+                Err(e) => log::warn!("Could not restore the saved game: {e}"),
+            }
+        }
+        match saved.game_mode {
+            GameModeSelector::PlayAgainsYourself => {
+                if in_progress {
+                    self.chessboard.resume_game();
+                }
+            }
+            GameModeSelector::PlayAgainsAI
+            | GameModeSelector::AiVsAi
+            | GameModeSelector::Analysis => {
+                self.restore = saved.engine_id.map(|engine_id| PendingRestore {
+                    engine_id,
+                    variant_name: saved.variant_name,
+                    // Engines playing each other are not restarted without the user
+                    resume_game: in_progress && saved.game_mode != GameModeSelector::AiVsAi,
+                });
+            }
+        }
+    }
+
+    /// Select the saved engine in a freshly fetched directory, or the first one if it is gone.
+    fn select_engine_from(&mut self, engines: &EngineDirectory) {
+        let saved = self.restore.as_ref().map(|r| r.engine_id.clone());
+        let restored = saved
+            .as_ref()
+            .and_then(|id| engines.engines.iter().find(|e| &e.engine_id == id));
+        if let (Some(id), None) = (&saved, restored) {
+            log::warn!("Saved engine {id} is not in the directory anymore");
+            self.restore_notice = Some(format!(
+                "The engine used last time ({id}) is not available anymore."
+            ));
+            self.restore = None;
+        }
+        self.engine_picker.selected_engine = Some(restored.unwrap_or(&engines.engines[0]).clone());
+        if self.second_engine_picker.selected_engine.is_none() {
+            self.second_engine_picker.selected_engine = Some(engines.engines[0].clone());
+        }
+    }
+
+    /// Select the saved variant once the engine description arrived,
+    /// and continue the saved game with it.
+    fn restore_variant(&mut self, desc: &EngineDescription) {
+        let Some(restore) = self.restore.take() else {
+            return;
+        };
+        let variant = restore
+            .variant_name
+            .as_ref()
+            .and_then(|name| desc.variants.iter().find(|v| &v.name == name));
+        match variant {
+            Some(variant) => {
+                self.engine_picker.variant = Some(variant.clone());
+                if restore.resume_game {
+                    self.chessboard.game_mode = self.new_game_mode();
+                    self.chessboard.resume_game();
+                }
+            }
+            None => {
+                if let Some(name) = restore.variant_name {
+                    self.restore_notice = Some(format!(
+                        "The variant used last time ({name}) is not available anymore."
+                    ));
+                }
+            }
         }
     }
 }
@@ -77,11 +286,94 @@ impl App {
                     ui.add_space(16.0);
                 }
 
+                ui.menu_button("Game", |ui| {
+                    if ui.button("Import PGN…").clicked() {
+                        ui.close_menu();
+                        self.pgn_import = Some(PgnImport::default());
+                    }
+                    if ui.button("Export PGN…").clicked() {
+                        ui.close_menu();
+                        self.export_pgn();
+                    }
+                });
+                ui.add_space(16.0);
+
                 egui::widgets::global_dark_light_mode_buttons(ui);
+                self.dark_mode = ui.visuals().dark_mode;
+
+                if let Some(err) = &self.file_error {
+                    ui.colored_label(ui.visuals().error_fg_color, err);
+                }
             });
         });
     }
 
+    fn export_pgn(&mut self) {
+        let pgn = self.chessboard.to_pgn();
+        self.file_error = files::save_text_file("game.pgn", &pgn)
+            .err()
+            .map(|e| format!("Could not export PGN: {e}"));
+    }
+
+    fn update_pgn_import_window(&mut self, ctx: &egui::Context) {
+        let Some(import) = &mut self.pgn_import else {
+            return;
+        };
+
+        if let Some(recv) = &import.file_receiver {
+            match recv.try_recv() {
+                Err(oneshot::TryRecvError::Empty) => {}
+                result => {
+                    match result {
+                        Ok(Ok(Some(text))) => import.text = text,
+                        Ok(Ok(None)) => {}
+                        Ok(Err(e)) => import.error = Some(format!("Could not read file: {e}")),
+                        Err(_) => import.error = Some("Could not read file".to_string()),
+                    }
+                    import.file_receiver = None;
+                }
+            }
+        }
+
+        let mut open = true;
+        let mut loaded_game = None;
+        Window::new("Import PGN")
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Open file…").clicked() {
+                        import.file_receiver = Some(files::open_text_file(ctx));
+                    }
+                    if ui.button("Load").clicked() {
+                        match chess::parse_pgn(&import.text) {
+                            Ok(game) => loaded_game = Some(game),
+                            Err(e) => import.error = Some(format!("Could not import PGN: {e}")),
+                        }
+                    }
+                });
+                if let Some(err) = &import.error {
+                    ui.colored_label(ui.visuals().error_fg_color, err);
+                }
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    ui.add(
+                        egui::TextEdit::multiline(&mut import.text)
+                            .code_editor()
+                            .hint_text("Paste a PGN here"),
+                    );
+                });
+            });
+
+        if let Some(game) = loaded_game {
+            self.stop_engine_games();
+            self.chessboard.load_game(game);
+            open = false;
+        }
+        if !open {
+            self.pgn_import = None;
+        }
+    }
+
     fn update_bottom_panel(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         egui::TopBottomPanel::bottom("footer").show(ctx, |ui| {
             ui.with_layout(egui::Layout::bottom_up(egui::Align::LEFT), |ui| {
@@ -91,32 +383,501 @@ impl App {
         });
     }
 
-    fn fetch_engine_description(&mut self) {
-        if let Some(selected_engine) = &self.engine_data.selected_engine {
-            let (sender, receiver) = oneshot::channel();
-            let req = RequestLoopComm::FetchEngineDescription(selected_engine.clone(), sender);
-            self.request_loop_sender
-                .try_send(req)
-                .expect("Error communicating with request loop");
-            self.engine_desc_receiver = Some(receiver);
+    fn update_left_panel(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::SidePanel::left("move_history").show(ctx, |ui| {
+            ui.heading("Moves");
+            if self.chessboard.can_play_from_displayed_position() {
+                if ui
+                    .add_enabled(self.can_start_game(), Button::new("Play from here"))
+                    .on_disabled_hover_text("Select an engine and variant first!")
+                    .clicked()
+                {
+                    self.stop_engine_games();
+                    self.chessboard.game_mode = self.new_game_mode();
+                    self.chessboard.play_from_displayed_position();
+                }
+            }
+            self.chessboard.show_move_input(ui);
+            self.chessboard.show_move_list(ui);
+        });
+    }
+
+    /// Starting position for a new game, or a readable reason why it is invalid.
+    fn new_game_position(&self) -> std::result::Result<Chess, String> {
+        let fen = self.start_fen.trim();
+        if fen.is_empty() {
+            return Ok(Chess::default());
+        }
+        Fen::from_ascii(fen.as_bytes())
+            .map_err(|e| format!("Invalid FEN: {e}"))?
+            .into_position(CastlingMode::Standard)
+            .map_err(|e| format!("Invalid position: {e}"))
+    }
+
+    /// Timeouts of every kind of request. Failed requests are retried a few times,
+    /// but moves and draw offers are not sent again after timing out.
+    fn update_request_timeouts(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Request timeouts", |ui| {
+            let mut timeouts = self.request_timeouts;
+            Grid::new("request_timeouts").show(ui, |ui| {
+                for (label, timeout) in [
+                    ("Engine list", &mut timeouts.engines),
+                    ("Engine description", &mut timeouts.engine_description),
+                    ("Move", &mut timeouts.pos_eval),
+                    ("Draw offer", &mut timeouts.draw_offer),
+                ] {
+                    ui.label(label);
+                    let mut secs = timeout.as_secs();
+                    if ui
+                        .add(
+                            egui::DragValue::new(&mut secs)
+                                .clamp_range(1..=600)
+                                .suffix(" s"),
+                        )
+                        .changed()
+                    {
+                        *timeout = Duration::from_secs(secs);
+                    }
+                    ui.end_row();
+                }
+            });
+            if timeouts != self.request_timeouts {
+                match requests::send_setting(
+                    &self.request_loop_sender,
+                    RequestLoopComm::SetTimeouts(timeouts),
+                ) {
+                    Ok(()) => {
+                        self.request_timeouts = timeouts;
+                        self.request_timeouts_error = None;
+                    }
+                    Err(e) => {
+                        self.request_timeouts_error =
+                            Some(format!("Could not change the timeouts: {e}"));
+                    }
+                }
+            }
+            if let Some(err) = &self.request_timeouts_error {
+                ui.colored_label(ui.visuals().error_fg_color, err);
+            }
+        });
+    }
+
+    /// Whether every engine the selected game mode needs has a variant selected.
+    fn can_start_game(&self) -> bool {
+        match self.game_mode_selection {
+            GameModeSelector::PlayAgainsAI | GameModeSelector::Analysis => {
+                self.engine_picker.variant.is_some()
+            }
+            GameModeSelector::PlayAgainsYourself => true,
+            GameModeSelector::AiVsAi => {
+                self.engine_picker.variant.is_some() && self.second_engine_picker.variant.is_some()
+            }
+        }
+    }
+
+    /// Game mode for a new game with the current settings.
+    /// Panics if an engine plays without a selected variant.
+    fn new_game_mode(&self) -> GameMode {
+        let ai_settings = |picker: &EnginePicker| {
+            AiGameSettings::new(
+                picker.player_name(),
+                picker
+                    .variant
+                    .clone()
+                    .expect("A variant must be selected to play against the AI"),
+                self.send_move_history,
+                self.request_loop_sender.clone(),
+            )
+        };
+        match self.game_mode_selection {
+            GameModeSelector::PlayAgainsAI => {
+                GameMode::PlayAgainsAI(ai_settings(&self.engine_picker))
+            }
+            GameModeSelector::PlayAgainsYourself => GameMode::PlayAgainsYourself,
+            GameModeSelector::AiVsAi => GameMode::AiVsAi(Box::new(AiVsAiSettings::new(
+                ai_settings(&self.engine_picker),
+                ai_settings(&self.second_engine_picker),
+            ))),
+            GameModeSelector::Analysis => {
+                GameMode::Analysis(Box::new(Analysis::new(ai_settings(&self.engine_picker))))
+            }
         }
     }
 
     fn fetch_engine_dir(&mut self) {
         // Build a request to the request loop.
-        let (sender, receiver) = oneshot::channel();
-        let req = RequestLoopComm::FetchEngines(sender);
-        self.request_loop_sender
-            .try_send(req)
-            .expect("Error communicating with request loop");
+        let (responder, receiver) = request_channel();
+        let req = RequestLoopComm::FetchEngines(responder);
+        requests::send_request(&self.request_loop_sender, req);
 
         self.engine_dir_receiver = Some(receiver);
+        self.engine_dir_error = None;
+    }
+
+    fn apply_api_url(&mut self) {
+        let url = self.api_url_input.trim().to_string();
+        if let Err(e) = reqwest::Url::parse(&url) {
+            self.api_url_error = Some(format!("Invalid URL: {e}"));
+            return;
+        }
+        self.api_url_error = None;
+        if url == self.api_url {
+            return;
+        }
+
+        if let Err(e) = requests::send_setting(
+            &self.request_loop_sender,
+            RequestLoopComm::SetApiUrl(url.clone()),
+        ) {
+            self.api_url_error = Some(format!("Could not change the API URL: {e}"));
+            return;
+        }
+        log::info!("API URL changed to: {url}");
+        self.api_url = url.clone();
+        self.api_url_input = url;
+
+        // Everything we know about engines came from the old directory.
+        self.restore = None;
+        self.stop_engine_games();
+        self.chessboard.stop_game();
+        self.available_engines = None;
+        self.engine_picker = EnginePicker::default();
+        self.second_engine_picker = EnginePicker::default();
+        self.fetch_engine_dir();
+    }
+
+    fn update_time_control_selection(&mut self, ui: &mut egui::Ui) {
+        let time_control = &mut self.chessboard.time_control;
+        ui.horizontal(|ui| {
+            ui.label("Time control");
+            egui::ComboBox::from_id_source("time_control_selection")
+                .selected_text(time_control.map_or("Unlimited".to_string(), |tc| tc.to_string()))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(time_control, None, "Unlimited");
+                    for preset in TimeControl::PRESETS {
+                        ui.selectable_value(time_control, Some(preset), preset.to_string());
+                    }
+                });
+        });
+
+        if let Some(tc) = time_control {
+            ui.horizontal(|ui| {
+                let mut minutes = tc.base.as_secs_f64() / 60.0;
+                if ui
+                    .add(
+                        egui::DragValue::new(&mut minutes)
+                            .clamp_range(0.25..=180.0)
+                            .speed(0.25)
+                            .suffix(" min"),
+                    )
+                    .changed()
+                {
+                    tc.base = Duration::from_secs_f64(minutes * 60.0);
+                }
+                let mut increment = tc.increment.as_secs();
+                if ui
+                    .add(
+                        egui::DragValue::new(&mut increment)
+                            .clamp_range(0..=180)
+                            .prefix("+")
+                            .suffix(" s"),
+                    )
+                    .changed()
+                {
+                    tc.increment = Duration::from_secs(increment);
+                }
+                let mut delay = tc.delay.as_secs();
+                if ui
+                    .add(
+                        egui::DragValue::new(&mut delay)
+                            .clamp_range(0..=60)
+                            .prefix("delay ")
+                            .suffix(" s"),
+                    )
+                    .changed()
+                {
+                    tc.delay = Duration::from_secs(delay);
+                }
+            });
+        }
+    }
+
+    /// Settings for a match between the two selected engines, and the button starting it.
+    fn update_match_settings(&mut self, ui: &mut egui::Ui, start_position: Option<Chess>) {
+        ui.collapsing("Match", |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Games");
+                ui.add(egui::DragValue::new(&mut self.match_settings.games).clamp_range(1..=1000));
+            });
+            ui.label("Openings").on_hover_text(
+                "One FEN per line, each played once with either color. \
+                 Empty to start every game from the position above.",
+            );
+            ui.add(
+                egui::TextEdit::multiline(&mut self.match_settings.openings)
+                    .code_editor()
+                    .desired_rows(3)
+                    .hint_text("One FEN per line"),
+            );
+            let openings = match match_runner::parse_openings(&self.match_settings.openings) {
+                Ok(openings) if openings.is_empty() => start_position
+                    .map(|position| vec![position])
+                    .ok_or_else(|| "Enter a valid FEN first!".to_string()),
+                result => result,
+            };
+            if let Err(err) = &openings {
+                ui.colored_label(ui.visuals().error_fg_color, err);
+            }
+            if ui
+                .add_enabled(
+                    self.can_start_game() && openings.is_ok(),
+                    Button::new("Start match"),
+                )
+                .on_disabled_hover_text("Select both engines and valid openings first!")
+                .clicked()
+            {
+                if let Ok(openings) = openings {
+                    self.start_match(openings);
+                }
+            }
+        });
+    }
+
+    /// Start a match between the two selected engines, replacing any previous one.
+    fn start_match(&mut self, openings: Vec<Chess>) {
+        let engine = |picker: &EnginePicker| MatchEngine {
+            name: picker.player_name(),
+            variant: picker
+                .variant
+                .clone()
+                .expect("A variant must be selected to play a match"),
+        };
+        let engines = [
+            engine(&self.engine_picker),
+            engine(&self.second_engine_picker),
+        ];
+        self.stop_engine_games();
+        self.restore = None;
+        self.match_runner = Some(MatchRunner::new(
+            engines,
+            openings,
+            self.match_settings.games,
+            self.send_move_history,
+            self.request_loop_sender.clone(),
+        ));
+    }
+
+    /// Stop the running match and pause the running tournament, keeping their results.
+    fn stop_engine_games(&mut self) {
+        if let Some(runner) = &mut self.match_runner {
+            runner.stop(&mut self.chessboard);
+        }
+        if let Some(runner) = &mut self.tournament_runner {
+            runner.pause(&mut self.chessboard);
+        }
+        self.participant_loader = None;
+    }
+
+    /// Settings for a tournament between every engine in the directory.
+    fn update_tournament_settings(&mut self, ui: &mut egui::Ui, start_position: Option<Chess>) {
+        ui.collapsing("Tournament", |ui| {
+            let settings = &mut self.tournament_settings;
+            ui.horizontal(|ui| {
+                ui.label("Format");
+                egui::ComboBox::from_id_source("tournament_format")
+                    .selected_text(settings.format.to_string())
+                    .show_ui(ui, |ui| {
+                        for format in [TournamentFormat::RoundRobin, TournamentFormat::Gauntlet] {
+                            ui.selectable_value(&mut settings.format, format, format.to_string());
+                        }
+                    });
+            });
+            if settings.format == TournamentFormat::Gauntlet {
+                ui.label("The engine selected for White plays every other engine.");
+            }
+            ui.horizontal(|ui| {
+                ui.label("Games per pairing");
+                ui.add(egui::DragValue::new(&mut settings.games_per_pairing).clamp_range(1..=100));
+            });
+            ui.checkbox(&mut settings.all_variants, "Every variant of each engine")
+                .on_hover_text("Otherwise each engine plays with its best variant");
+
+            let can_start = self.available_engines.is_some()
+                && start_position.is_some()
+                && (self.tournament_settings.format == TournamentFormat::RoundRobin
+                    || self.engine_picker.variant.is_some());
+            if ui
+                .add_enabled(can_start, Button::new("Start tournament"))
+                .on_disabled_hover_text("Load the engine list and enter a valid FEN first!")
+                .clicked()
+            {
+                self.start_tournament();
+            }
+            if let Some(err) = &self.tournament_error {
+                ui.colored_label(ui.visuals().error_fg_color, err);
+            }
+        });
+    }
+
+    /// Fetch every engine's description, the tournament starts once they arrived.
+    fn start_tournament(&mut self) {
+        // Checked again once the descriptions arrived, as the settings may change meanwhile
+        if let Err(err) = self.new_game_position() {
+            self.tournament_error = Some(err);
+            return;
+        }
+        if self.tournament_settings.format == TournamentFormat::Gauntlet
+            && self.engine_picker.variant.is_none()
+        {
+            self.tournament_error = Some(GAUNTLET_WITHOUT_VARIANT.to_string());
+            return;
+        }
+        let Some(engines) = &self.available_engines else {
+            return;
+        };
+        let loader = ParticipantLoader::new(
+            &engines.engines,
+            self.tournament_settings.all_variants,
+            &self.request_loop_sender,
+        );
+        self.stop_engine_games();
+        self.restore = None;
+        self.tournament_error = None;
+        self.tournament_runner = None;
+        self.participant_loader = Some(loader);
+    }
+
+    /// Start the tournament once every participant is known.
+    fn update_participant_loader(&mut self) {
+        let Some(loader) = &mut self.participant_loader else {
+            return;
+        };
+        if !loader.poll() {
+            return;
+        }
+        let Some(loader) = self.participant_loader.take() else {
+            return;
+        };
+        let mut participants = loader.participants();
+        if self.tournament_settings.format == TournamentFormat::Gauntlet {
+            let Some(variant) = self.engine_picker.variant.clone() else {
+                self.tournament_error = Some(GAUNTLET_WITHOUT_VARIANT.to_string());
+                return;
+            };
+            let challenger = MatchEngine {
+                name: self.engine_picker.player_name(),
+                variant,
+            };
+            participants.retain(|p| p.name != challenger.name);
+            participants.insert(0, challenger);
+        }
+        if participants.len() < 2 {
+            self.tournament_error = Some(format!(
+                "A tournament needs at least two engines. {}",
+                loader.errors().join(" ")
+            ));
+            return;
+        }
+        let start_position = match self.new_game_position() {
+            Ok(position) => position,
+            Err(err) => {
+                self.tournament_error = Some(err);
+                return;
+            }
+        };
+        let tournament = Tournament::new(
+            self.tournament_settings.format,
+            participants,
+            self.tournament_settings.games_per_pairing,
+            &start_position,
+        );
+        self.tournament_error = (!loader.errors().is_empty()).then(|| loader.errors().join("\n"));
+        self.tournament_runner = Some(TournamentRunner::new(
+            tournament,
+            false,
+            self.send_move_history,
+            self.request_loop_sender.clone(),
+        ));
+    }
+
+    fn update_tournament_window(&mut self, ctx: &egui::Context) {
+        if self.participant_loader.is_none() && self.tournament_runner.is_none() {
+            return;
+        }
+        let mut open = true;
+        let mut resume = false;
+        Window::new("Tournament").open(&mut open).show(ctx, |ui| {
+            if let Some(runner) = &mut self.tournament_runner {
+                if let Some(err) = &self.tournament_error {
+                    ui.colored_label(ui.visuals().warn_fg_color, err);
+                }
+                resume = runner.show(ui, &mut self.chessboard);
+            } else {
+                ui.horizontal(|ui| {
+                    ui.label("Fetching engine descriptions...");
+                    ui.spinner();
+                });
+            }
+        });
+        if resume {
+            // The tournament's next game takes over the board
+            self.stop_engine_games();
+            self.chessboard.stop_game();
+            self.game_mode_selection = GameModeSelector::AiVsAi;
+            if let Some(runner) = &mut self.tournament_runner {
+                runner.resume();
+            }
+        }
+        if !open {
+            self.stop_engine_games();
+            self.tournament_runner = None;
+        }
+    }
+
+    fn update_match_window(&mut self, ctx: &egui::Context) {
+        let Some(runner) = &mut self.match_runner else {
+            return;
+        };
+        let mut open = true;
+        Window::new("Match").open(&mut open).show(ctx, |ui| {
+            if runner.is_running() && ui.button("Stop match").clicked() {
+                runner.stop(&mut self.chessboard);
+            }
+            runner.show_results(ui);
+        });
+        if !open {
+            self.stop_engine_games();
+            self.match_runner = None;
+        }
     }
 
     fn update_right_panel(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         egui::SidePanel::right("engine_info").show(ctx, |ui| {
+            if let Some(editor) = &mut self.board_editor {
+                ui.heading("Set up position");
+                editor.show_controls(ctx, ui);
+                let fen = editor.position().is_ok().then(|| editor.fen().to_string());
+                let (use_position, cancel) = ui
+                    .horizontal(|ui| {
+                        let use_position = ui
+                            .add_enabled(fen.is_some(), Button::new("Use position"))
+                            .on_disabled_hover_text("The position is not valid")
+                            .clicked();
+                        (use_position, ui.button("Cancel").clicked())
+                    })
+                    .inner;
+                if use_position {
+                    self.start_fen = fen.unwrap_or_default();
+                    self.board_editor = None;
+                } else if cancel {
+                    self.board_editor = None;
+                }
+                return;
+            }
+
             ui.heading("Game settings");
 
+            let previous_mode = self.game_mode_selection;
             egui::ComboBox::from_id_source("gamemode_selection")
                 .width(140f32)
                 .selected_text(format!("{}", self.game_mode_selection))
@@ -141,8 +902,35 @@ impl App {
                     {
                         self.chessboard.stop_game()
                     }
+                    if ui
+                        .selectable_value(
+                            &mut self.game_mode_selection,
+                            GameModeSelector::AiVsAi,
+                            format!("{}", GameModeSelector::AiVsAi),
+                        )
+                        .clicked()
+                    {
+                        self.chessboard.stop_game()
+                    }
+                    if ui
+                        .selectable_value(
+                            &mut self.game_mode_selection,
+                            GameModeSelector::Analysis,
+                            format!("{}", GameModeSelector::Analysis),
+                        )
+                        .clicked()
+                    {
+                        self.chessboard.stop_game()
+                    }
                 });
 
+            if self.game_mode_selection != previous_mode {
+                self.stop_engine_games();
+                if self.game_mode_selection == GameModeSelector::PlayAgainsYourself {
+                    self.chessboard.game_mode = GameMode::PlayAgainsYourself;
+                }
+            }
+
             ui.horizontal(|ui| {
                 ui.horizontal(|ui| {
                     if ui
@@ -174,160 +962,272 @@ impl App {
                 })
             });
 
-            if self.game_mode_selection == GameModeSelector::PlayAgainsYourself {
-                self.chessboard.game_mode = GameMode::PlayAgainsYourself;
-            } else {
+            if self.game_mode_selection != GameModeSelector::Analysis {
+                self.update_time_control_selection(ui);
+            }
+            ui.horizontal(|ui| {
+                ui.label("Move animation");
+                ui.add(
+                    egui::Slider::new(&mut self.chessboard.animation_duration, 0.0..=1.0)
+                        .suffix(" s"),
+                )
+                .on_hover_text("Set to 0 to turn animations off");
+            });
+
+            ui.label("Start from FEN");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.start_fen)
+                    .hint_text("Standard starting position"),
+            );
+            let new_game_position = self.new_game_position();
+            if let Err(err) = &new_game_position {
+                ui.colored_label(ui.visuals().error_fg_color, err);
+            }
+            if ui.button("Edit position…").clicked() {
+                self.stop_engine_games();
+                self.chessboard.stop_game();
+                self.board_editor = Some(chess::BoardEditor::from_fen(&self.start_fen));
+            }
+
+            if self.game_mode_selection != GameModeSelector::PlayAgainsYourself {
                 ui.heading("Select engine");
+                ui.horizontal(|ui| {
+                    ui.label("API URL");
+                    let resp = ui.text_edit_singleline(&mut self.api_url_input);
+                    if ui.button("Apply").clicked()
+                        || (resp.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)))
+                    {
+                        self.apply_api_url();
+                    }
+                });
+                if let Some(err) = &self.api_url_error {
+                    ui.colored_label(ui.visuals().error_fg_color, err);
+                }
+                if let Some(notice) = &self.restore_notice {
+                    ui.colored_label(ui.visuals().warn_fg_color, notice);
+                }
                 if ui.button("Update info").clicked() || self.fetch_engine_list_first_boot {
-                    self.engine_data.available_engines = None;
+                    self.available_engines = None;
                     self.fetch_engine_dir();
                     self.fetch_engine_list_first_boot = false;
                 }
 
                 if let Some(recv) = &self.engine_dir_receiver {
-                    if let Ok(Ok(engines)) = recv.try_recv() {
-                        self.engine_data.available_engines = Some(engines.clone());
-                        self.engine_data.selected_engine = Some(engines.engines[0].clone());
-                        self.engine_dir_receiver = None;
-                    } else {
-                        ui.label("Loading engine list...");
-                        ui.spinner();
+                    match recv.try_recv() {
+                        Err(oneshot::TryRecvError::Empty) => {
+                            ui.label("Loading engine list...");
+                            ui.spinner();
+                        }
+                        Ok(Ok(engines)) if engines.engines.is_empty() => {
+                            self.engine_dir_error =
+                                Some("The engine directory does not list any engines".to_string());
+                            self.engine_dir_receiver = None;
+                        }
+                        Ok(Ok(engines)) => {
+                            self.select_engine_from(&engines);
+                            self.available_engines = Some(engines);
+                            self.engine_dir_receiver = None;
+                        }
+                        Ok(Err(e)) => {
+                            log::error!("Could not fetch the engine list: {e:#}");
+                            self.engine_dir_error =
+                                Some(format!("Could not fetch the engine list: {e:#}"));
+                            self.engine_dir_receiver = None;
+                        }
+                        Err(oneshot::TryRecvError::Disconnected) => {
+                            self.engine_dir_error =
+                                Some("The request for the engine list was dropped".to_string());
+                            self.engine_dir_receiver = None;
+                        }
                     }
                 }
-                if let Some(data) = self.engine_data.selected_engine.as_mut() {
-                    let cbox_resp = egui::ComboBox::from_id_source("engine_selection")
-                        .selected_text(data.name.to_string())
-                        .show_ui(ui, |ui| {
-                            let mut is_clicked = false;
-                            for engine in self
-                                .engine_data
-                                .available_engines
-                                .as_mut()
-                                .unwrap()
-                                .engines
-                                .iter()
-                            {
-                                if ui
-                                    .selectable_value(data, engine.clone(), engine.name.clone())
-                                    .clicked()
-                                {
-                                    is_clicked = true;
-                                }
-                            }
-                            is_clicked
-                        });
-                    let selected_engine = data.clone();
-
-                    if cbox_resp.inner.is_some_and(|v| v) {
-                        log::info!("Engine changed to: {data:?}");
-                        self.chessboard.stop_game();
-                        self.engine_data.variant = None;
-                        self.engine_data.desc = None;
-                        self.fetch_engine_description();
+                if let Some(err) = &self.engine_dir_error {
+                    if show_error_with_retry(ui, err) {
+                        self.fetch_engine_dir();
                     }
-
-                    Grid::new("current_engine_info").show(ui, |ui| {
-                        ui.label("Name");
-                        ui.label(selected_engine.name);
-                        ui.end_row();
-                        ui.label("Id");
-                        ui.label(selected_engine.engine_id);
-                        ui.end_row();
-                        ui.label("URL");
-                        ui.hyperlink(selected_engine.entrypoint_url);
-                        ui.end_row();
-                    });
                 }
-
-                if self.engine_data.selected_engine.is_some() {
-                    ui.heading("Select variant");
-                    if ui.button("Update info").clicked() {
-                        self.fetch_engine_description();
-                    }
-                    if self.engine_data.desc.is_none() && self.engine_desc_receiver.is_none() {
-                        self.fetch_engine_description();
+                let engines = self
+                    .available_engines
+                    .as_ref()
+                    .map_or(&[][..], |dir| &dir.engines);
+                let is_ai_vs_ai = self.game_mode_selection == GameModeSelector::AiVsAi;
+                if is_ai_vs_ai {
+                    ui.label(RichText::new("White").strong());
+                }
+                let change = ui
+                    .push_id("first_engine", |ui| {
+                        self.engine_picker
+                            .show(ui, engines, &self.request_loop_sender)
+                    })
+                    .inner;
+                if is_ai_vs_ai {
+                    ui.separator();
+                    ui.label(RichText::new("Black").strong());
+                    let second_change = ui
+                        .push_id("second_engine", |ui| {
+                            self.second_engine_picker
+                                .show(ui, engines, &self.request_loop_sender)
+                        })
+                        .inner;
+                    if let Some(PickerChange::Selection) = second_change {
+                        self.stop_engine_games();
+                        self.chessboard.stop_game();
                     }
-                    if let Some(recv) = &self.engine_desc_receiver {
-                        if let Ok(Ok(desc)) = recv.try_recv() {
-                            log::info!("Received engine description: {desc:?}");
-                            self.engine_data.desc = Some(desc.clone());
-                            self.engine_data.variant = None;
-                            self.engine_desc_receiver = None;
-                        } else {
-                            ui.label("Loading engine description...");
-                            ui.spinner();
-                        }
+                }
+                match change {
+                    Some(PickerChange::Selection) => {
+                        self.restore = None;
+                        self.stop_engine_games();
+                        self.chessboard.stop_game();
                     }
-                    if let Some(desc) = &mut self.engine_data.desc {
-                        ui.heading(desc.name.clone());
-                        ui.add(Label::new(desc.text_description.clone()).wrap(true));
-
-                        if self.engine_data.variant.is_none() {
-                            self.engine_data.variant = Some(desc.best_available_variant.clone());
-                        }
-
-                        let mut checkpoint = self.engine_data.variant.as_ref().unwrap().clone();
-                        if egui::ComboBox::from_id_source("variant_selection")
-                            .selected_text(checkpoint.name.to_string())
+                    Some(PickerChange::Description(desc)) => self.restore_variant(&desc),
+                    None => {}
+                }
+                if self.engine_picker.desc.is_some() {
+                    ui.checkbox(&mut self.send_move_history, "Send move history")
+                        .on_hover_text(
+                            "Send the starting position and all moves, \
+                             so the engine can see repetitions. \
+                             Engines that do not support it get only the current position.",
+                        );
+                    self.update_request_timeouts(ui);
+                    ui.horizontal(|ui| {
+                        ui.label("Invalid moves").on_hover_text(
+                            "When the engine replies with a move that cannot be played",
+                        );
+                        egui::ComboBox::from_id_source("invalid_move_handling")
+                            .selected_text(self.chessboard.invalid_move_handling.to_string())
                             .show_ui(ui, |ui| {
-                                let mut is_clicked = false;
-                                for variant in &desc.variants {
-                                    is_clicked |= ui
-                                        .selectable_value(
-                                            &mut checkpoint,
-                                            variant.clone(),
-                                            variant.name.clone(),
-                                        )
-                                        .clicked();
+                                for handling in
+                                    [InvalidMoveHandling::Forfeit, InvalidMoveHandling::Requery]
+                                {
+                                    ui.selectable_value(
+                                        &mut self.chessboard.invalid_move_handling,
+                                        handling,
+                                        handling.to_string(),
+                                    );
                                 }
-                                is_clicked
-                            })
-                            .inner
-                            .is_some_and(|v| v)
-                        {
-                            log::info!("Changed variant: new is {checkpoint:?}");
-                            self.engine_data.variant = Some(checkpoint);
-                            self.chessboard.stop_game()
-                        }
-
-                        if let Some(chk) = &self.engine_data.variant {
-                            ui.label(&chk.description);
-                        }
-                    }
+                            });
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Draw offers")
+                            .on_hover_text("Used when the engine cannot answer draw offers");
+                        egui::ComboBox::from_id_source("draw_offer_fallback")
+                            .selected_text(self.chessboard.draw_offer_fallback.to_string())
+                            .show_ui(ui, |ui| {
+                                for fallback in [
+                                    DrawOfferFallback::Material,
+                                    DrawOfferFallback::Accept,
+                                    DrawOfferFallback::Decline,
+                                ] {
+                                    ui.selectable_value(
+                                        &mut self.chessboard.draw_offer_fallback,
+                                        fallback,
+                                        fallback.to_string(),
+                                    );
+                                }
+                            });
+                    });
                 }
             }
+            let match_start_position = new_game_position.clone().ok();
             match self.game_mode_selection {
-                GameModeSelector::PlayAgainsAI => {
-                    if let Some(variant) = &self.engine_data.variant {
-                        if ui.button("Play vs AI").clicked() {
+                GameModeSelector::PlayAgainsAI
+                | GameModeSelector::AiVsAi
+                | GameModeSelector::Analysis => {
+                    let label = match self.game_mode_selection {
+                        GameModeSelector::AiVsAi => "Start AI vs AI",
+                        GameModeSelector::Analysis => "Start analysis",
+                        _ => "Play vs AI",
+                    };
+                    if self.can_start_game() {
+                        if ui
+                            .add_enabled(new_game_position.is_ok(), Button::new(label))
+                            .on_disabled_hover_text("Enter a valid FEN first!")
+                            .clicked()
+                        {
                             log::info!("Starting AI game!");
-                            self.chessboard.game_mode =
-                                GameMode::PlayAgainsAI(AiGameSettings::new(
-                                    variant.clone(),
-                                    self.request_loop_sender.clone(),
-                                ));
-                            self.chessboard.start_game();
+                            self.stop_engine_games();
+                            self.restore = None;
+                            self.chessboard.game_mode = self.new_game_mode();
+                            if let Ok(position) = new_game_position {
+                                self.chessboard.start_game_from(position);
+                            }
                         }
                     } else {
-                        ui.add_enabled(false, Button::new("Play vs AI"))
+                        ui.add_enabled(false, Button::new(label))
                             .on_disabled_hover_text("Select an engine and variant first!");
                     }
                 }
                 GameModeSelector::PlayAgainsYourself => {
-                    if ui.button("Start game").clicked() {
+                    if ui
+                        .add_enabled(new_game_position.is_ok(), Button::new("Start game"))
+                        .on_disabled_hover_text("Enter a valid FEN first!")
+                        .clicked()
+                    {
                         log::info!("Starting self game!");
+                        self.stop_engine_games();
+                        self.restore = None;
                         self.chessboard.game_mode = GameMode::PlayAgainsYourself;
-                        self.chessboard.start_game();
+                        if let Ok(position) = new_game_position {
+                            self.chessboard.start_game_from(position);
+                        }
                     }
                 }
             }
+            if self.game_mode_selection == GameModeSelector::AiVsAi {
+                self.update_match_settings(ui, match_start_position.clone());
+                self.update_tournament_settings(ui, match_start_position);
+            }
 
             ui.separator();
 
+            self.chessboard.show_clocks(ui);
+            self.chessboard.show_ai_vs_ai_controls(ui);
+            self.chessboard.show_analysis(ui);
+            if let Some(draw) = self.chessboard.claimable_draw() {
+                if ui
+                    .button("Claim draw")
+                    .on_hover_text(draw.description())
+                    .clicked()
+                {
+                    self.chessboard.claim_draw();
+                }
+            }
+            if self.chessboard.game_is_going() && self.chessboard.has_player() {
+                ui.horizontal(|ui| {
+                    if ui.button("Resign").clicked() {
+                        self.chessboard.resign();
+                    }
+                    if ui
+                        .add_enabled(self.chessboard.can_offer_draw(), Button::new("Offer draw"))
+                        .clicked()
+                    {
+                        self.chessboard.offer_draw();
+                    }
+                });
+            }
+            if let Some(status) = self.chessboard.draw_offer_status() {
+                ui.label(status);
+            }
             if self.chessboard.is_waiting_for_ai_move() {
                 ui.label("Waiting for server's move...");
                 ui.spinner();
             }
+            if let Some(err) = self.chessboard.ai_move_error() {
+                if show_error_with_retry(ui, err) {
+                    self.chessboard.retry_ai_move();
+                }
+            }
+            if let Some(invalid) = self.chessboard.invalid_ai_move() {
+                ui.colored_label(ui.visuals().error_fg_color, &invalid.error);
+                ui.collapsing("Engine response", |ui| {
+                    ui.add(
+                        Label::new(RichText::new(format!("{:#?}", invalid.response)).monospace())
+                            .wrap(true),
+                    );
+                });
+            }
             if let Some(status) = self.chessboard.last_ai_move_info() {
                 Grid::new("ai_move_table").show(ui, |ui| {
                     ui.heading("Latest AI move");
@@ -346,7 +1246,8 @@ impl App {
         });
 
         if let Some(term) = self.chessboard.get_termination() {
-            if self.chessboard.game_over_is_dismissed() {
+            // Analysis goes on by taking moves back or browsing the history
+            if self.chessboard.game_over_is_dismissed() || self.chessboard.is_analysis() {
                 return;
             }
             Window::new("game_over_modal")
@@ -377,27 +1278,83 @@ impl App {
         egui::CentralPanel::default().show(ctx, |ui| {
             // The central panel the region left after adding TopPanel's and SidePanel's
             ui.heading("Unchessful Games");
-            self.chessboard.update_ai_move();
+            self.chessboard.update_clock(ctx);
+            self.chessboard.update_ai_move(ctx);
+            self.chessboard.update_analysis();
+            self.chessboard.update_draw_offer();
             egui::Area::new("board_area")
                 .anchor(Align2::CENTER_CENTER, [0f32, 0f32])
                 .movable(false)
                 .show(ctx, |ui| {
-                    self.chessboard.show(ctx, ui);
+                    if let Some(editor) = &mut self.board_editor {
+                        editor.show_board(ctx, ui);
+                    } else {
+                        self.chessboard.show(ctx, ui);
+                    }
                 });
         });
     }
 }
 
 impl eframe::App for App {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, API_URL_KEY, &self.api_url);
+        let state = SavedState {
+            game_mode: self.game_mode_selection,
+            player_is_white: self.chessboard.player_color == Color::White,
+            engine_id: self
+                .engine_picker
+                .selected_engine
+                .as_ref()
+                .map(|e| e.engine_id.clone())
+                .or_else(|| self.restore.as_ref().map(|r| r.engine_id.clone())),
+            variant_name: self
+                .engine_picker
+                .variant
+                .as_ref()
+                .map(|v| v.name.clone())
+                .or_else(|| self.restore.as_ref().and_then(|r| r.variant_name.clone())),
+            dark_mode: self.dark_mode,
+            start_fen: self.start_fen.clone(),
+            game: self
+                .chessboard
+                .has_game()
+                .then(|| SavedGame::from(&self.chessboard)),
+            tournament: self
+                .tournament_runner
+                .as_ref()
+                .map(|runner| runner.tournament.clone()),
+        };
+        eframe::set_value(storage, STATE_KEY, &state);
+    }
+
     /// Called each time the UI needs repainting, which may be many times per second.
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // Before the panels, so a finished match game never shows the game over popup
+        if let Some(runner) = &mut self.match_runner {
+            runner.update(&mut self.chessboard);
+        }
+        self.update_participant_loader();
+        if let Some(runner) = &mut self.tournament_runner {
+            runner.update(&mut self.chessboard);
+        }
         self.update_top_panel(ctx, _frame);
         self.update_bottom_panel(ctx, _frame);
         self.update_right_panel(ctx, _frame);
+        self.update_left_panel(ctx, _frame);
         self.update_central_panel(ctx, _frame);
+        self.update_pgn_import_window(ctx);
+        self.update_match_window(ctx);
+        self.update_tournament_window(ctx);
     }
 }
 
+/// Show a failed request's error, returns whether the user asked to retry it.
+fn show_error_with_retry(ui: &mut egui::Ui, err: &str) -> bool {
+    ui.colored_label(ui.visuals().error_fg_color, err);
+    ui.button("Retry").clicked()
+}
+
 fn powered_by_egui_and_eframe(ui: &mut egui::Ui) {
     ui.horizontal(|ui| {
         ui.spacing_mut().item_spacing.x = 0.0;
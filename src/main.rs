@@ -6,6 +6,8 @@
 fn main() -> eframe::Result<()> {
     env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
 
+    let api_url = api_url_from_args();
+
     let native_options = eframe::NativeOptions {
         initial_window_size: Some([400.0, 300.0].into()),
         min_window_size: Some([300.0, 220.0].into()),
@@ -14,13 +16,28 @@ fn main() -> eframe::Result<()> {
     eframe::run_native(
         "Unchessful Games",
         native_options,
-        Box::new(|cc| {
+        Box::new(move |cc| {
             egui_extras::install_image_loaders(&cc.egui_ctx);
-            Box::new(chess_ai_frontend::App::new(cc))
+            Box::new(chess_ai_frontend::App::new(cc, api_url))
         }),
     )
 }
 
+/// Read the engine directory root from `--api-url <url>` or `--api-url=<url>`.
+#[cfg(not(target_arch = "wasm32"))]
+fn api_url_from_args() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if let Some(url) = arg.strip_prefix("--api-url=") {
+            return Some(url.to_string());
+        }
+        if arg == "--api-url" {
+            return args.next();
+        }
+    }
+    None
+}
+
 // When compiling to web using trunk:
 #[cfg(target_arch = "wasm32")]
 fn main() {
@@ -36,10 +53,29 @@ fn main() {
                 web_options,
                 Box::new(|cc| {
                     egui_extras::install_image_loaders(&cc.egui_ctx);
-                    Box::new(chess_ai_frontend::App::new(cc))
+                    // `?api_url=...` takes precedence over `<meta name="api-url" content="...">`
+                    let api_url = cc
+                        .integration_info
+                        .web_info
+                        .location
+                        .query_map
+                        .get("api_url")
+                        .cloned()
+                        .or_else(api_url_from_meta_tag);
+                    Box::new(chess_ai_frontend::App::new(cc, api_url))
                 }),
             )
             .await
             .expect("failed to start eframe");
     });
 }
+
+/// Read the engine directory root from `<meta name="api-url" content="...">`.
+#[cfg(target_arch = "wasm32")]
+fn api_url_from_meta_tag() -> Option<String> {
+    web_sys::window()?
+        .document()?
+        .query_selector("meta[name='api-url']")
+        .ok()??
+        .get_attribute("content")
+}
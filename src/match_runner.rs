@@ -0,0 +1,494 @@
+use egui::{Grid, RichText};
+use serde::{Deserialize, Serialize};
+use shakmaty::{fen::Fen, CastlingMode, Chess, Color, Outcome};
+use tokio::sync::mpsc;
+use web_types::EngineVariant;
+
+use crate::{
+    chess::{AiGameSettings, AiVsAiSettings, ChessBoard, GameMode, Termination},
+    files,
+    requests::RequestLoopComm,
+};
+
+/// An engine variant taking part in a match, with the name used in results.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct MatchEngine {
+    pub name: String,
+    pub variant: EngineVariant,
+}
+
+/// Settings for the next match, edited before it is started.
+pub(crate) struct MatchSettings {
+    pub games: usize,
+    /// Starting positions as FEN, one per line, the standard position if empty
+    pub openings: String,
+}
+
+impl Default for MatchSettings {
+    fn default() -> Self {
+        Self {
+            games: 10,
+            openings: String::new(),
+        }
+    }
+}
+
+/// A finished game of a match.
+struct MatchGame {
+    /// Whether the first engine played White
+    first_is_white: bool,
+    opening: String,
+    outcome: Outcome,
+    termination: &'static str,
+    plies: usize,
+    pgn: String,
+}
+
+impl MatchGame {
+    /// Points the first engine got from this game.
+    fn first_score(&self) -> f64 {
+        match self.outcome {
+            Outcome::Draw => 0.5,
+            Outcome::Decisive { winner } if (winner == Color::White) == self.first_is_white => 1.0,
+            Outcome::Decisive { .. } => 0.0,
+        }
+    }
+}
+
+/// Elo difference of the first engine over the second, with a 95% confidence interval.
+/// Bounds are infinite when the score interval reaches 0% or 100%.
+struct EloEstimate {
+    diff: f64,
+    lower: f64,
+    upper: f64,
+}
+
+impl EloEstimate {
+    /// Estimate from the points the first engine got in each game.
+    fn from_scores(scores: &[f64]) -> Option<Self> {
+        if scores.is_empty() {
+            return None;
+        }
+        let n = scores.len() as f64;
+        let mean = scores.iter().sum::<f64>() / n;
+        let variance = scores.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / n;
+        let margin = 1.96 * (variance / n).sqrt();
+        Some(Self {
+            diff: elo_from_score(mean),
+            lower: elo_from_score((mean - margin).max(0.0)),
+            upper: elo_from_score((mean + margin).min(1.0)),
+        })
+    }
+}
+
+/// Elo difference that makes `score` the expected share of points.
+fn elo_from_score(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+fn format_elo(elo: f64) -> String {
+    if elo == f64::INFINITY {
+        "+∞".to_string()
+    } else if elo == f64::NEG_INFINITY {
+        "−∞".to_string()
+    } else {
+        format!("{elo:+.0}")
+    }
+}
+
+/// Plays the games of a match or tournament on the board, one at a time.
+pub(crate) struct EngineGames {
+    /// Index of the game on the board, `None` between games
+    current_game: Option<usize>,
+    send_move_history: bool,
+    sender: mpsc::Sender<RequestLoopComm>,
+}
+
+impl EngineGames {
+    pub fn new(send_move_history: bool, sender: mpsc::Sender<RequestLoopComm>) -> Self {
+        Self {
+            current_game: None,
+            send_move_history,
+            sender,
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.current_game.is_some()
+    }
+
+    /// Start game `index` between `white` and `black` from `position`.
+    pub fn start(
+        &mut self,
+        board: &mut ChessBoard,
+        index: usize,
+        white: &MatchEngine,
+        black: &MatchEngine,
+        position: Chess,
+    ) {
+        let settings = |engine: &MatchEngine| {
+            AiGameSettings::new(
+                engine.name.clone(),
+                engine.variant.clone(),
+                self.send_move_history,
+                self.sender.clone(),
+            )
+        };
+        board.game_mode = GameMode::AiVsAi(Box::new(AiVsAiSettings::new(
+            settings(white),
+            settings(black),
+        )));
+        board.start_game_from(position);
+        self.current_game = Some(index);
+    }
+
+    /// The index and termination of the game on the board once it is over.
+    /// The board stays as the game ended, for recording it.
+    pub fn finished(&mut self, board: &mut ChessBoard) -> Option<(usize, Termination)> {
+        let index = self.current_game?;
+        let termination = board.get_termination()?;
+        // Games follow each other without the game over popup
+        board.dismiss_game_over();
+        self.current_game = None;
+        Some((index, termination))
+    }
+
+    /// Stop the game on the board, if one is being played.
+    pub fn abandon(&mut self, board: &mut ChessBoard) {
+        if self.current_game.take().is_some() {
+            board.stop_game();
+        }
+    }
+}
+
+/// Read one FEN per line, skipping empty lines.
+pub(crate) fn parse_openings(text: &str) -> Result<Vec<Chess>, String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .enumerate()
+        .map(|(idx, line)| {
+            Fen::from_ascii(line.as_bytes())
+                .map_err(|e| format!("Opening {}: invalid FEN: {e}", idx + 1))?
+                .into_position(CastlingMode::Standard)
+                .map_err(|e| format!("Opening {}: invalid position: {e}", idx + 1))
+        })
+        .collect()
+}
+
+/// A series of games between two engine variants, played one after the other on the board.
+/// Colors alternate every game, and each opening is played once with either color.
+pub(crate) struct MatchRunner {
+    engines: [MatchEngine; 2],
+    openings: Vec<Chess>,
+    games: usize,
+    results: Vec<MatchGame>,
+    board_games: EngineGames,
+    stopped: bool,
+    export_error: Option<String>,
+}
+
+impl MatchRunner {
+    /// `openings` must not be empty.
+    pub fn new(
+        engines: [MatchEngine; 2],
+        openings: Vec<Chess>,
+        games: usize,
+        send_move_history: bool,
+        sender: mpsc::Sender<RequestLoopComm>,
+    ) -> Self {
+        log::info!(
+            "Starting a {games} game match: {} vs {}",
+            engines[0].name,
+            engines[1].name
+        );
+        Self {
+            engines,
+            openings,
+            games,
+            results: Vec::new(),
+            board_games: EngineGames::new(send_move_history, sender),
+            stopped: false,
+            export_error: None,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.stopped && self.results.len() < self.games
+    }
+
+    /// Stop after the game on the board, which is not counted.
+    pub fn stop(&mut self, board: &mut ChessBoard) {
+        if self.is_running() {
+            log::info!("Match stopped after {} games", self.results.len());
+            self.stopped = true;
+            self.board_games.abandon(board);
+        }
+    }
+
+    fn first_is_white(index: usize) -> bool {
+        index % 2 == 0
+    }
+
+    /// Each opening is played twice in a row, once with either color.
+    fn opening_index(index: usize, openings: usize) -> usize {
+        (index / 2) % openings
+    }
+
+    /// Record the game on the board once it is over, and start the next one.
+    pub fn update(&mut self, board: &mut ChessBoard) {
+        if !self.is_running() {
+            return;
+        }
+        if !self.board_games.is_playing() {
+            self.start_game(board, self.results.len());
+            return;
+        }
+        let Some((index, termination)) = self.board_games.finished(board) else {
+            return;
+        };
+        let first_is_white = Self::first_is_white(index);
+        let pgn = board.to_pgn_for_event("Engine match", &(index + 1).to_string());
+        let history = board.move_history();
+        self.results.push(MatchGame {
+            first_is_white,
+            opening: history.start_fen.to_string(),
+            outcome: termination.outcome(),
+            termination: termination.description(),
+            plies: history.moves.len(),
+            pgn,
+        });
+    }
+
+    fn names(&self, first_is_white: bool) -> (&str, &str) {
+        let [first, second] = &self.engines;
+        if first_is_white {
+            (&first.name, &second.name)
+        } else {
+            (&second.name, &first.name)
+        }
+    }
+
+    fn start_game(&mut self, board: &mut ChessBoard, index: usize) {
+        let [first, second] = &self.engines;
+        let (white, black) = if Self::first_is_white(index) {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        let opening = self.openings[Self::opening_index(index, self.openings.len())].clone();
+        self.board_games.start(board, index, white, black, opening);
+    }
+
+    fn scores(&self) -> Vec<f64> {
+        self.results.iter().map(MatchGame::first_score).collect()
+    }
+
+    /// Results as CSV, one row per game.
+    fn to_csv(&self) -> String {
+        let mut csv = "game,white,black,result,termination,plies,opening\n".to_string();
+        for (idx, game) in self.results.iter().enumerate() {
+            let (white, black) = self.names(game.first_is_white);
+            let row = [
+                (idx + 1).to_string(),
+                white.to_string(),
+                black.to_string(),
+                game.outcome.to_string(),
+                game.termination.to_string(),
+                game.plies.to_string(),
+                game.opening.clone(),
+            ];
+            let row: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+            csv += &row.join(",");
+            csv.push('\n');
+        }
+        csv
+    }
+
+    fn to_pgn(&self) -> String {
+        self.results
+            .iter()
+            .map(|game| game.pgn.as_str())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Score, Elo estimate and the table of finished games.
+    pub fn show_results(&mut self, ui: &mut egui::Ui) {
+        let [first, second] = &self.engines;
+        ui.heading(format!("{} vs {}", first.name, second.name));
+
+        let scores = self.scores();
+        let wins = scores.iter().filter(|s| **s > 0.75).count();
+        let draws = scores.iter().filter(|s| (0.25..0.75).contains(*s)).count();
+        let losses = scores.len() - wins - draws;
+        ui.label(format!(
+            "{}: +{wins} ={draws} -{losses}, {} / {} points",
+            first.name,
+            scores.iter().sum::<f64>(),
+            scores.len()
+        ));
+        if let Some(elo) = EloEstimate::from_scores(&scores) {
+            ui.label(format!(
+                "Elo difference: {} (95% confidence: {} to {})",
+                format_elo(elo.diff),
+                format_elo(elo.lower),
+                format_elo(elo.upper)
+            ))
+            .on_hover_text(format!("Positive when {} is stronger", first.name));
+        }
+        if self.is_running() {
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "Playing game {} of {}",
+                    self.results.len() + 1,
+                    self.games
+                ));
+                ui.spinner();
+            });
+        } else if self.stopped {
+            ui.label(format!(
+                "Stopped after {} of {} games",
+                self.results.len(),
+                self.games
+            ));
+        } else {
+            ui.label("The match is over");
+        }
+
+        ui.horizontal(|ui| {
+            let has_results = !self.results.is_empty();
+            if ui
+                .add_enabled(has_results, egui::Button::new("Export CSV…"))
+                .clicked()
+            {
+                self.export_error = files::save_text_file("match.csv", &self.to_csv())
+                    .err()
+                    .map(|e| format!("Could not export CSV: {e}"));
+            }
+            if ui
+                .add_enabled(has_results, egui::Button::new("Export PGN…"))
+                .clicked()
+            {
+                self.export_error = files::save_text_file("match.pgn", &self.to_pgn())
+                    .err()
+                    .map(|e| format!("Could not export PGN: {e}"));
+            }
+        });
+        if let Some(err) = &self.export_error {
+            ui.colored_label(ui.visuals().error_fg_color, err);
+        }
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            Grid::new("match_results").striped(true).show(ui, |ui| {
+                for header in ["#", "White", "Black", "Result", "Termination"] {
+                    ui.label(RichText::new(header).strong());
+                }
+                ui.end_row();
+                for (idx, game) in self.results.iter().enumerate() {
+                    let (white, black) = self.names(game.first_is_white);
+                    ui.label((idx + 1).to_string());
+                    ui.label(white);
+                    ui.label(black);
+                    ui.label(game.outcome.to_string().replace("1/2", "½"))
+                        .on_hover_text(&game.opening);
+                    ui.label(game.termination);
+                    ui.end_row();
+                }
+            });
+        });
+    }
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains(|c| matches!(c, ',' | '"' | '\n')) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::Position;
+
+    use super::*;
+
+    #[test]
+    fn elo_is_zero_for_an_even_score() {
+        let elo = EloEstimate::from_scores(&[1.0, 0.0, 0.5, 0.5]).unwrap();
+        assert!(elo.diff.abs() < 1e-9);
+        assert!(elo.lower < 0.0 && elo.upper > 0.0);
+        assert!((elo.lower + elo.upper).abs() < 1e-6);
+    }
+
+    #[test]
+    fn elo_is_infinite_for_a_perfect_score() {
+        let wins = EloEstimate::from_scores(&[1.0; 5]).unwrap();
+        assert_eq!(wins.diff, f64::INFINITY);
+        assert_eq!(wins.lower, f64::INFINITY);
+        let losses = EloEstimate::from_scores(&[0.0; 5]).unwrap();
+        assert_eq!(losses.diff, f64::NEG_INFINITY);
+        assert_eq!(losses.upper, f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn elo_bound_is_infinite_when_the_interval_reaches_full_score() {
+        let elo = EloEstimate::from_scores(&[1.0, 1.0, 1.0, 0.5]).unwrap();
+        assert!(elo.diff.is_finite() && elo.diff > 0.0);
+        assert!(elo.lower.is_finite() && elo.lower < elo.diff);
+        assert_eq!(elo.upper, f64::INFINITY);
+    }
+
+    #[test]
+    fn elo_needs_games() {
+        assert!(EloEstimate::from_scores(&[]).is_none());
+    }
+
+    #[test]
+    fn elo_formatting() {
+        assert_eq!(format_elo(f64::INFINITY), "+∞");
+        assert_eq!(format_elo(f64::NEG_INFINITY), "−∞");
+        assert_eq!(format_elo(0.0), "+0");
+        assert_eq!(format_elo(-12.4), "-12");
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn openings_skip_empty_lines() {
+        assert!(parse_openings("").unwrap().is_empty());
+        let openings = parse_openings(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1\n\n  \n\
+             rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1\n",
+        )
+        .unwrap();
+        assert_eq!(openings.len(), 2);
+        assert_eq!(openings[0].turn(), Color::White);
+        assert_eq!(openings[1].turn(), Color::Black);
+    }
+
+    #[test]
+    fn invalid_openings_are_reported_by_number() {
+        let err = parse_openings(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1\n\nnot a fen\n",
+        )
+        .unwrap_err();
+        assert!(err.starts_with("Opening 2:"), "{err}");
+    }
+
+    #[test]
+    fn colors_alternate_and_openings_repeat_once() {
+        let first_is_white: Vec<bool> = (0..4).map(MatchRunner::first_is_white).collect();
+        assert_eq!(first_is_white, [true, false, true, false]);
+        let openings: Vec<usize> = (0..6).map(|i| MatchRunner::opening_index(i, 2)).collect();
+        assert_eq!(openings, [0, 0, 1, 1, 0, 0]);
+    }
+}
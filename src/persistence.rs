@@ -0,0 +1,133 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use shakmaty::{fen::Fen, uci::Uci, CastlingMode, Chess, Color, Position};
+
+use crate::{
+    chess::{ChessBoard, GameTags, ImportedGame, Termination},
+    tournament::Tournament,
+    GameModeSelector,
+};
+
+/// Storage key under which [`SavedState`] is persisted.
+pub(crate) const STATE_KEY: &str = "app_state";
+
+/// Settings and the game in progress, restored on the next start.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SavedState {
+    pub game_mode: GameModeSelector,
+    pub player_is_white: bool,
+    /// `engine_id` of the selected engine, looked up again in the refreshed directory
+    pub engine_id: Option<String>,
+    pub variant_name: Option<String>,
+    pub dark_mode: bool,
+    pub start_fen: String,
+    pub game: Option<SavedGame>,
+    /// Missing in state saved before tournaments existed
+    #[serde(default)]
+    pub tournament: Option<Tournament>,
+}
+
+/// A game as its starting position and the moves played from it.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SavedGame {
+    pub start_fen: String,
+    pub moves: Vec<String>,
+    /// Missing in games saved before the players were recorded
+    #[serde(default)]
+    pub tags: GameTags,
+    /// Only games in progress are continued, finished or stopped ones are shown for replay
+    #[serde(default)]
+    pub in_progress: bool,
+    /// How the game ended, when it was not decided on the board
+    #[serde(default)]
+    pub termination: Option<SavedTermination>,
+}
+
+impl From<&ChessBoard> for SavedGame {
+    fn from(board: &ChessBoard) -> Self {
+        let history = board.move_history();
+        Self {
+            start_fen: history.start_fen.to_string(),
+            moves: history.moves.iter().map(ToString::to_string).collect(),
+            tags: board.tags().clone(),
+            in_progress: board.is_in_progress(),
+            termination: board
+                .declared_termination()
+                .and_then(SavedTermination::from_termination),
+        }
+    }
+}
+
+impl SavedGame {
+    /// Replay the saved moves, which may fail if the storage was tampered with.
+    pub fn to_game(&self) -> Result<ImportedGame> {
+        let start_position: Chess =
+            Fen::from_ascii(self.start_fen.as_bytes())?.into_position(CastlingMode::Standard)?;
+        let mut position = start_position.clone();
+        let mut moves = Vec::with_capacity(self.moves.len());
+        for uci in &self.moves {
+            let m = Uci::from_ascii(uci.as_bytes())?
+                .to_move(&position)
+                .map_err(|e| anyhow!("Saved move {uci} is not valid: {e}"))?;
+            position.play_unchecked(&m);
+            moves.push(m);
+        }
+        Ok(ImportedGame {
+            start_position,
+            moves,
+            tags: self.tags.clone(),
+        })
+    }
+}
+
+/// A [`Termination`] that cannot be seen on the board, so it has to be saved.
+/// The side is White if `white` is set.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum SavedTermination {
+    ThreefoldRepetition,
+    FiftyMoveRule,
+    Resignation { white: bool },
+    DrawAgreed,
+    Timeout { white: bool },
+    TimeoutVsInsufficientMaterial { white: bool },
+    EngineForfeit { white: bool },
+}
+
+impl SavedTermination {
+    /// `None` for terminations decided on the board, which are detected again on restore.
+    fn from_termination(termination: Termination) -> Option<Self> {
+        let white = |color: Color| color == Color::White;
+        Some(match termination {
+            Termination::ThreefoldRepetition => Self::ThreefoldRepetition,
+            Termination::FiftyMoveRule => Self::FiftyMoveRule,
+            Termination::Resignation(c) => Self::Resignation { white: white(c) },
+            Termination::DrawAgreed => Self::DrawAgreed,
+            Termination::Timeout(c) => Self::Timeout { white: white(c) },
+            Termination::TimeoutVsInsufficientMaterial(c) => {
+                Self::TimeoutVsInsufficientMaterial { white: white(c) }
+            }
+            Termination::EngineForfeit(c) => Self::EngineForfeit { white: white(c) },
+            Termination::Checkmate(_)
+            | Termination::Stalemate(_)
+            | Termination::InsufficientMaterial
+            | Termination::FivefoldRepetition
+            | Termination::SeventyFiveMoveRule
+            | Termination::Unknown(_) => return None,
+        })
+    }
+
+    pub fn to_termination(self) -> Termination {
+        let color = |white: bool| if white { Color::White } else { Color::Black };
+        match self {
+            Self::ThreefoldRepetition => Termination::ThreefoldRepetition,
+            Self::FiftyMoveRule => Termination::FiftyMoveRule,
+            Self::Resignation { white } => Termination::Resignation(color(white)),
+            Self::DrawAgreed => Termination::DrawAgreed,
+            Self::Timeout { white } => Termination::Timeout(color(white)),
+            Self::TimeoutVsInsufficientMaterial { white } => {
+                Termination::TimeoutVsInsufficientMaterial(color(white))
+            }
+            Self::EngineForfeit { white } => Termination::EngineForfeit(color(white)),
+        }
+    }
+}
@@ -1,77 +1,578 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    future::Future,
+    pin::pin,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
 use egui::Context;
-use tokio::sync::mpsc;
+use futures::future::{select, Either};
+use futures_timer::Delay;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    Semaphore,
+};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use poll_promise::Promise;
-use shakmaty::fen::Fen;
+use shakmaty::{fen::Fen, uci::Uci};
 use web_types::*;
 
+/// Engine directory root used when nothing else was configured.
+pub const DEFAULT_API_URL: &str = "https://api.unchessful.games/";
+
+/// Attempts made for a request failing with transient errors, including the first one.
+const MAX_ATTEMPTS: u32 = 3;
+/// Wait before the first retry, doubled for each further one.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+/// Requests handled at the same time, more wait in the channel.
+const MAX_IN_FLIGHT: usize = 4;
+/// Why a message could not be handed to the request loop.
+const SEND_FULL_ERROR: &str = "Too many requests are waiting, try again";
+const SEND_CLOSED_ERROR: &str = "The request loop has stopped";
+
 #[derive(Debug)]
 pub enum RequestLoopComm {
-    FetchEngines(oneshot::Sender<Result<EngineDirectory>>),
-    FetchEngineDescription(EngineRef, oneshot::Sender<Result<EngineDescription>>),
-    FetchPosEval(
-        EngineVariant,
-        Fen,
-        oneshot::Sender<Result<GameMoveResponse>>,
-    ),
+    /// Change the engine directory root used by all subsequent requests.
+    SetApiUrl(String),
+    /// Change the timeouts used by all subsequent requests.
+    SetTimeouts(RequestTimeouts),
+    FetchEngines(Responder<EngineDirectory>),
+    FetchEngineDescription(EngineRef, Responder<EngineDescription>),
+    FetchPosEval(PosEvalRequest, Responder<GameMoveResponse>),
+    /// Ask the engine whether it accepts a draw in the given position.
+    OfferDraw(PosEvalRequest, Responder<bool>),
+}
+
+impl RequestLoopComm {
+    /// The game a request belongs to, whose requests must be handled in order.
+    fn game_id(&self) -> Option<u64> {
+        match self {
+            RequestLoopComm::FetchPosEval(req, _) | RequestLoopComm::OfferDraw(req, _) => {
+                Some(req.game_id)
+            }
+            _ => None,
+        }
+    }
+
+    /// Answer the request with `error` without handling it.
+    fn fail(self, error: anyhow::Error) {
+        match self {
+            RequestLoopComm::SetApiUrl(_) | RequestLoopComm::SetTimeouts(_) => {}
+            RequestLoopComm::FetchEngines(responder) => responder.send(Err(error)),
+            RequestLoopComm::FetchEngineDescription(_, responder) => responder.send(Err(error)),
+            RequestLoopComm::FetchPosEval(_, responder) => responder.send(Err(error)),
+            RequestLoopComm::OfferDraw(_, responder) => responder.send(Err(error)),
+        }
+    }
+}
+
+/// A new identifier for [`PosEvalRequest::game_id`].
+pub fn next_game_id() -> u64 {
+    static NEXT_GAME_ID: AtomicU64 = AtomicU64::new(1);
+    NEXT_GAME_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// How long a single attempt of each kind of request may take.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RequestTimeouts {
+    pub engines: Duration,
+    pub engine_description: Duration,
+    /// Engines may think for a long time
+    pub pos_eval: Duration,
+    pub draw_offer: Duration,
+}
+
+impl Default for RequestTimeouts {
+    fn default() -> Self {
+        Self {
+            engines: Duration::from_secs(10),
+            engine_description: Duration::from_secs(10),
+            pos_eval: Duration::from_secs(60),
+            draw_offer: Duration::from_secs(30),
+        }
+    }
+}
+
+/// The request loop's end of a request.
+#[derive(Debug)]
+pub struct Responder<T> {
+    sender: oneshot::Sender<Result<T>>,
+    /// Completes when the [`PendingRequest`] is cancelled or dropped
+    cancel: oneshot::Receiver<()>,
+}
+
+impl<T> Responder<T> {
+    fn send(self, result: Result<T>) {
+        let _ = self.sender.send(result);
+    }
+}
+
+/// The UI's end of a request.
+/// Dropping it cancels the request, so a late reply can never be applied.
+#[derive(Debug)]
+pub struct PendingRequest<T> {
+    receiver: oneshot::Receiver<Result<T>>,
+    _cancel: oneshot::Sender<()>,
+}
+
+impl<T> PendingRequest<T> {
+    pub fn try_recv(&self) -> Result<Result<T>, oneshot::TryRecvError> {
+        self.receiver.try_recv()
+    }
+
+    /// Abort the request. Same as dropping it, but says what is meant.
+    pub fn cancel(self) {}
+}
+
+/// Create both ends of a request.
+pub fn request_channel<T>() -> (Responder<T>, PendingRequest<T>) {
+    let (sender, receiver) = oneshot::channel();
+    let (cancel_sender, cancel_receiver) = oneshot::channel();
+    (
+        Responder {
+            sender,
+            cancel: cancel_receiver,
+        },
+        PendingRequest {
+            receiver,
+            _cancel: cancel_sender,
+        },
+    )
+}
+
+/// A request attempt took longer than its timeout.
+#[derive(Debug)]
+struct TimedOut(Duration);
+
+impl Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No response within {:?}", self.0)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// The UI cancelled the request.
+#[derive(Debug)]
+struct Cancelled;
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The request was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Everything the engine is told about the position it has to move in.
+#[derive(Debug, Clone)]
+pub struct PosEvalRequest {
+    /// Requests of the same game are handled in the order they were sent
+    pub game_id: u64,
+    pub variant: EngineVariant,
+    pub fen: Fen,
+    /// Only sent when the game is timed
+    pub clock: Option<ClockInfo>,
+    /// Only sent when enabled for the game
+    pub history: Option<MoveHistory>,
+}
+
+/// Starting position and the moves played since, so that engines
+/// can detect repetitions and use history-dependent heuristics.
+#[derive(Debug, Clone)]
+pub struct MoveHistory {
+    pub start_fen: Fen,
+    pub moves: Vec<Uci>,
 }
 
-pub fn run_request_loop(ctx: Context) -> mpsc::Sender<RequestLoopComm> {
+/// Remaining time and increments of both sides, like UCI `go wtime btime winc binc`.
+#[derive(Debug, Clone, Copy)]
+pub struct ClockInfo {
+    pub wtime: Duration,
+    pub btime: Duration,
+    pub winc: Duration,
+    pub binc: Duration,
+}
+
+/// [`GameMoveRequest`] with optional extensions.
+///
+/// Fields that are not set are left out of the JSON entirely,
+/// so backends that do not know about them keep working.
+/// Backends that reject the move history are sent the request without it.
+/// The clock fields are always kept.
+#[derive(Serialize)]
+struct ExtendedGameMoveRequest {
+    #[serde(flatten)]
+    request: GameMoveRequest,
+    /// Milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wtime: Option<u64>,
+    /// Milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    btime: Option<u64>,
+    /// Milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    winc: Option<u64>,
+    /// Milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    binc: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start_fen: Option<String>,
+    /// UCI notation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    moves: Option<Vec<String>>,
+}
+
+impl ExtendedGameMoveRequest {
+    /// The request with the clocks but without the move history.
+    fn without_history(req: &PosEvalRequest) -> Self {
+        Self {
+            start_fen: None,
+            moves: None,
+            ..Self::from(req)
+        }
+    }
+}
+
+impl From<&PosEvalRequest> for ExtendedGameMoveRequest {
+    fn from(req: &PosEvalRequest) -> Self {
+        let millis =
+            |f: fn(&ClockInfo) -> Duration| req.clock.as_ref().map(|c| f(c).as_millis() as u64);
+        Self {
+            request: GameMoveRequest {
+                fen: req.fen.to_string(),
+            },
+            wtime: millis(|c| c.wtime),
+            btime: millis(|c| c.btime),
+            winc: millis(|c| c.winc),
+            binc: millis(|c| c.binc),
+            start_fen: req.history.as_ref().map(|h| h.start_fen.to_string()),
+            moves: req
+                .history
+                .as_ref()
+                .map(|h| h.moves.iter().map(Uci::to_string).collect()),
+        }
+    }
+}
+
+pub fn run_request_loop(
+    ctx: Context,
+    api_url: String,
+    timeouts: RequestTimeouts,
+) -> mpsc::Sender<RequestLoopComm> {
     let (request_sender, mut request_receiver) = mpsc::channel::<RequestLoopComm>(10);
     let _ = Promise::spawn_local(async move {
+        let mut api_url = api_url;
+        let mut timeouts = timeouts;
+        // Game URLs of engines that rejected the move history
+        let no_history_engines = Rc::new(RefCell::new(HashSet::new()));
+        let in_flight = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
+        // Completes when the latest request of each game is done
+        let mut game_queues: HashMap<u64, oneshot::Receiver<()>> = HashMap::new();
         while let Some(comm) = request_receiver.recv().await {
             log::debug!("Received request: {comm:?}");
-            match comm {
-                RequestLoopComm::FetchEngines(response_sender) => {
-                    let resp = get_engines().await;
-                    log::info!("Received engine directory result: {resp:?}");
-                    let _ = response_sender.send(resp);
+            let comm = match comm {
+                RequestLoopComm::SetApiUrl(url) => {
+                    log::info!("API URL changed to: {url}");
+                    api_url = url;
+                    continue;
                 }
-                RequestLoopComm::FetchEngineDescription(engine_ref, response_sender) => {
-                    let resp = get_engine_description(engine_ref.clone()).await;
-                    log::info!("Received engine description result: {resp:?}");
-                    let _ = response_sender.send(resp);
+                RequestLoopComm::SetTimeouts(new_timeouts) => {
+                    log::info!("Request timeouts changed to: {new_timeouts:?}");
+                    timeouts = new_timeouts;
+                    continue;
                 }
-                RequestLoopComm::FetchPosEval(engine_variant, fen, response_sender) => {
-                    let resp = get_position_evaluation(engine_variant.clone(), fen.clone()).await;
-                    log::info!("Received game move result: {resp:?}");
-                    let _ = response_sender.send(resp);
+                comm => comm,
+            };
+
+            // Requests of the same game are handled one after another, in order
+            let (previous, done) = match comm.game_id() {
+                Some(game_id) => {
+                    game_queues.retain(|_, waiter| {
+                        !matches!(waiter.try_recv(), Err(oneshot::TryRecvError::Disconnected))
+                    });
+                    let (done, waiter) = oneshot::channel::<()>();
+                    (game_queues.insert(game_id, waiter), Some(done))
                 }
-            }
-            ctx.request_repaint();
+                None => (None, None),
+            };
+
+            let ctx = ctx.clone();
+            let api_url = api_url.clone();
+            let no_history_engines = no_history_engines.clone();
+            let in_flight = in_flight.clone();
+            let _ = Promise::spawn_local(async move {
+                if let Some(previous) = previous {
+                    // Fails once the previous request is done and drops its sender
+                    let _ = previous.await;
+                }
+                // Take a slot only when ready to send, so requests queued behind
+                // their game's previous one don't hold back other games
+                let permit = in_flight
+                    .acquire_owned()
+                    .await
+                    .expect("The semaphore is never closed");
+                handle_request(comm, &api_url, timeouts, &no_history_engines).await;
+                drop(done);
+                drop(permit);
+                ctx.request_repaint();
+            });
         }
     });
     request_sender
 }
 
-async fn get_engines() -> Result<EngineDirectory> {
-    Ok(reqwest::get("https://api.unchessful.games/")
-        .await?
-        .json()
-        .await?)
+async fn handle_request(
+    comm: RequestLoopComm,
+    api_url: &str,
+    timeouts: RequestTimeouts,
+    no_history_engines: &RefCell<HashSet<String>>,
+) {
+    match comm {
+        RequestLoopComm::SetApiUrl(_) | RequestLoopComm::SetTimeouts(_) => {
+            unreachable!("Settings are applied by the request loop itself")
+        }
+        RequestLoopComm::FetchEngines(mut responder) => {
+            let resp = with_retries(timeouts.engines, true, &mut responder.cancel, || {
+                get_engines(api_url)
+            })
+            .await;
+            log::info!("Received engine directory result: {resp:?}");
+            responder.send(resp);
+        }
+        RequestLoopComm::FetchEngineDescription(engine_ref, mut responder) => {
+            let resp = with_retries(
+                timeouts.engine_description,
+                true,
+                &mut responder.cancel,
+                || get_engine_description(api_url, &engine_ref),
+            )
+            .await;
+            log::info!("Received engine description result: {resp:?}");
+            responder.send(resp);
+        }
+        RequestLoopComm::FetchPosEval(req, mut responder) => {
+            let resp = with_retries(timeouts.pos_eval, false, &mut responder.cancel, || {
+                get_position_evaluation(api_url, &req, no_history_engines)
+            })
+            .await;
+            log::info!("Received game move result: {resp:?}");
+            responder.send(resp);
+        }
+        RequestLoopComm::OfferDraw(req, mut responder) => {
+            let resp = with_retries(timeouts.draw_offer, false, &mut responder.cancel, || {
+                offer_draw(api_url, &req)
+            })
+            .await;
+            log::info!("Received draw offer result: {resp:?}");
+            responder.send(resp);
+        }
+    }
+}
+
+/// Hand `comm` to the request loop without blocking the UI.
+/// If the loop is full or gone, the request fails right away through its own responder.
+pub fn send_request(sender: &mpsc::Sender<RequestLoopComm>, comm: RequestLoopComm) {
+    let (comm, error) = match sender.try_send(comm) {
+        Ok(()) => return,
+        Err(TrySendError::Full(comm)) => (comm, SEND_FULL_ERROR),
+        Err(TrySendError::Closed(comm)) => (comm, SEND_CLOSED_ERROR),
+    };
+    log::error!("Could not send {comm:?}: {error}");
+    comm.fail(anyhow!(error));
+}
+
+/// Hand a settings change to the request loop without blocking the UI.
+/// Settings have no responder, so a failure is returned instead,
+/// and the caller must not assume the change was applied.
+pub fn send_setting(sender: &mpsc::Sender<RequestLoopComm>, comm: RequestLoopComm) -> Result<()> {
+    let (comm, error) = match sender.try_send(comm) {
+        Ok(()) => return Ok(()),
+        Err(TrySendError::Full(comm)) => (comm, SEND_FULL_ERROR),
+        Err(TrySendError::Closed(comm)) => (comm, SEND_CLOSED_ERROR),
+    };
+    log::error!("Could not send {comm:?}: {error}");
+    Err(anyhow!(error))
+}
+
+/// Run a request made by `attempt`, retrying transient failures with exponential backoff.
+/// Each attempt may take up to `timeout`, and the request is abandoned once `cancel` completes.
+///
+/// Timeouts are only retried if `retry_timeouts` is set. A POST that timed out may still
+/// be handled by the engine, and sending it again would make it think twice.
+async fn with_retries<T, Fut>(
+    timeout: Duration,
+    retry_timeouts: bool,
+    cancel: &mut oneshot::Receiver<()>,
+    mut attempt: impl FnMut() -> Fut,
+) -> Result<T>
+where
+    Fut: Future<Output = Result<T>>,
+{
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempts = 1;
+    loop {
+        let result = match select(pin!(attempt()), select(Delay::new(timeout), &mut *cancel)).await
+        {
+            Either::Left((result, _)) => result,
+            Either::Right((Either::Left(_), _)) => Err(TimedOut(timeout).into()),
+            Either::Right((Either::Right(_), _)) => return Err(Cancelled.into()),
+        };
+        match result {
+            Err(e)
+                if attempts < MAX_ATTEMPTS
+                    && is_transient(&e)
+                    && (retry_timeouts || !is_timeout(&e)) =>
+            {
+                log::warn!("Request failed ({e:#}), retrying in {backoff:?}");
+                if let Either::Right(_) = select(Delay::new(backoff), &mut *cancel).await {
+                    return Err(Cancelled.into());
+                }
+                backoff *= 2;
+                attempts += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_timeout(e: &anyhow::Error) -> bool {
+    e.is::<TimedOut>()
+        || e.downcast_ref::<reqwest::Error>()
+            .is_some_and(reqwest::Error::is_timeout)
 }
 
-async fn get_engine_description(engine_ref: EngineRef) -> Result<EngineDescription> {
-    Ok(reqwest::get(engine_ref.entrypoint_url)
+/// Whether trying the same request again might succeed:
+/// network failures, timeouts, and server errors.
+fn is_transient(e: &anyhow::Error) -> bool {
+    if e.is::<TimedOut>() {
+        return true;
+    }
+    let Some(e) = e.downcast_ref::<reqwest::Error>() else {
+        return false;
+    };
+    e.is_timeout()
+        || e.is_connect()
+        || e.is_request()
+        || e.status().is_some_and(|status| {
+            status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        })
+}
+
+/// Resolve `url` against the engine directory root.
+/// Absolute URLs are returned unchanged, relative ones are joined onto `api_url`.
+fn resolve_url(api_url: &str, url: &str) -> Result<reqwest::Url> {
+    Ok(reqwest::Url::parse(api_url)?.join(url)?)
+}
+
+async fn get_engines(api_url: &str) -> Result<EngineDirectory> {
+    Ok(reqwest::get(reqwest::Url::parse(api_url)?)
         .await?
+        .error_for_status()?
         .json()
         .await?)
 }
 
+async fn get_engine_description(
+    api_url: &str,
+    engine_ref: &EngineRef,
+) -> Result<EngineDescription> {
+    let url = resolve_url(api_url, &engine_ref.entrypoint_url)?;
+    Ok(reqwest::get(url).await?.error_for_status()?.json().await?)
+}
+
+/// Whether a backend answered with `status` because it does not accept a field of the request.
+/// Other client errors, like too many requests, say nothing about the fields.
+fn rejects_fields(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::BAD_REQUEST
+        || status == reqwest::StatusCode::UNPROCESSABLE_ENTITY
+}
+
 async fn get_position_evaluation(
-    engine_varian: EngineVariant,
-    fen: Fen,
+    api_url: &str,
+    req: &PosEvalRequest,
+    no_history_engines: &RefCell<HashSet<String>>,
 ) -> Result<GameMoveResponse> {
     let client = reqwest::Client::new();
-    let data = GameMoveRequest {
-        fen: fen.to_string(),
-    };
+    let url = resolve_url(api_url, &req.variant.game_url)?;
+
+    if req.history.is_some() && !no_history_engines.borrow().contains(url.as_str()) {
+        let resp = client
+            .post(url.clone())
+            .json(&ExtendedGameMoveRequest::from(req))
+            .send()
+            .await?;
+        if !rejects_fields(resp.status()) {
+            return Ok(resp.error_for_status()?.json().await?);
+        }
+        let status = resp.status();
+        let rejection = resp.error_for_status().unwrap_err();
+        // Only blame the history if the engine answers once it is left out,
+        // a request it rejects for another reason keeps its own error
+        let resp = send_without_history(&client, url.clone(), req).await;
+        match &resp {
+            Err(e) if is_transient(e) => return resp,
+            Err(_) => return Err(rejection.into()),
+            Ok(_) => {}
+        }
+        log::warn!("{url} rejected the move history with {status}, sending the position only");
+        no_history_engines.borrow_mut().insert(url.to_string());
+        return resp;
+    }
+
+    send_without_history(&client, url, req).await
+}
+
+async fn send_without_history(
+    client: &reqwest::Client,
+    url: reqwest::Url,
+    req: &PosEvalRequest,
+) -> Result<GameMoveResponse> {
     Ok(client
-        .post(engine_varian.game_url)
-        .json(&data)
+        .post(url)
+        .json(&ExtendedGameMoveRequest::without_history(req))
         .send()
         .await?
+        .error_for_status()?
         .json()
         .await?)
 }
+
+#[derive(Deserialize)]
+struct DrawOfferResponse {
+    accept: bool,
+}
+
+/// Draw offers are POSTed to `<game_url>/draw_offer` with the same body as a move request,
+/// and answered with `{"accept": <bool>}`.
+async fn offer_draw(api_url: &str, req: &PosEvalRequest) -> Result<bool> {
+    let mut url = resolve_url(api_url, &req.variant.game_url)?;
+    url.path_segments_mut()
+        .map_err(|_| {
+            anyhow!(
+                "Cannot derive a draw offer URL from {}",
+                req.variant.game_url
+            )
+        })?
+        .pop_if_empty()
+        .push("draw_offer");
+
+    let resp: DrawOfferResponse = reqwest::Client::new()
+        .post(url)
+        .json(&ExtendedGameMoveRequest::from(req))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(resp.accept)
+}
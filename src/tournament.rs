@@ -0,0 +1,540 @@
+use std::fmt::Display;
+
+use egui::{Grid, RichText};
+use serde::{Deserialize, Serialize};
+use shakmaty::{fen::Fen, CastlingMode, Chess, Color, Outcome};
+use tokio::sync::mpsc;
+use web_types::{EngineDescription, EngineRef};
+
+use crate::{
+    chess::ChessBoard,
+    match_runner::{EngineGames, MatchEngine},
+    requests::{self, request_channel, PendingRequest, RequestLoopComm},
+};
+
+/// Who plays whom.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum TournamentFormat {
+    /// Everyone plays everyone else
+    RoundRobin,
+    /// The selected engine plays everyone else
+    Gauntlet,
+}
+
+impl Display for TournamentFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TournamentFormat::RoundRobin => write!(f, "Round robin"),
+            TournamentFormat::Gauntlet => write!(f, "Gauntlet"),
+        }
+    }
+}
+
+/// Settings for the next tournament, edited before it is started.
+pub(crate) struct TournamentSettings {
+    pub format: TournamentFormat,
+    /// Games each pairing plays, with colors alternating
+    pub games_per_pairing: usize,
+    /// Enter every variant of each engine, not only its best one
+    pub all_variants: bool,
+}
+
+impl Default for TournamentSettings {
+    fn default() -> Self {
+        Self {
+            format: TournamentFormat::RoundRobin,
+            games_per_pairing: 2,
+            all_variants: false,
+        }
+    }
+}
+
+/// Descriptions of every engine in the directory, fetched before a tournament can start.
+pub(crate) struct ParticipantLoader {
+    engines: Vec<(EngineRef, Option<PendingRequest<EngineDescription>>)>,
+    descriptions: Vec<Option<EngineDescription>>,
+    errors: Vec<String>,
+    all_variants: bool,
+}
+
+impl ParticipantLoader {
+    pub fn new(
+        engines: &[EngineRef],
+        all_variants: bool,
+        sender: &mpsc::Sender<RequestLoopComm>,
+    ) -> Self {
+        let engines: Vec<_> = engines
+            .iter()
+            .map(|engine| {
+                let (responder, receiver) = request_channel();
+                let req = RequestLoopComm::FetchEngineDescription(engine.clone(), responder);
+                requests::send_request(sender, req);
+                (engine.clone(), Some(receiver))
+            })
+            .collect();
+        Self {
+            descriptions: engines.iter().map(|_| None).collect(),
+            engines,
+            errors: Vec::new(),
+            all_variants,
+        }
+    }
+
+    /// Collect the descriptions that arrived, returns whether all of them are in.
+    /// Engines whose description cannot be fetched are left out.
+    pub fn poll(&mut self) -> bool {
+        for ((engine, receiver), desc) in self.engines.iter_mut().zip(&mut self.descriptions) {
+            let Some(recv) = receiver else {
+                continue;
+            };
+            match recv.try_recv() {
+                Err(oneshot::TryRecvError::Empty) => continue,
+                Ok(Ok(received)) => *desc = Some(received),
+                Ok(Err(e)) => self
+                    .errors
+                    .push(format!("{} is left out: {e:#}", engine.name)),
+                Err(oneshot::TryRecvError::Disconnected) => self.errors.push(format!(
+                    "{} is left out: the request was dropped",
+                    engine.name
+                )),
+            }
+            *receiver = None;
+        }
+        self.engines.iter().all(|(_, receiver)| receiver.is_none())
+    }
+
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    /// Every engine variant taking part, in directory order.
+    pub fn participants(&self) -> Vec<MatchEngine> {
+        let mut participants = Vec::new();
+        for ((engine, _), desc) in self.engines.iter().zip(&self.descriptions) {
+            let Some(desc) = desc else {
+                continue;
+            };
+            let variants = if self.all_variants {
+                desc.variants.clone()
+            } else {
+                vec![desc.best_available_variant.clone()]
+            };
+            for variant in variants {
+                participants.push(MatchEngine {
+                    name: format!("{} ({})", engine.name, variant.name),
+                    variant,
+                });
+            }
+        }
+        participants
+    }
+}
+
+/// Result of a finished tournament game.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+}
+
+impl From<Outcome> for GameResult {
+    fn from(outcome: Outcome) -> Self {
+        match outcome {
+            Outcome::Decisive {
+                winner: Color::White,
+            } => GameResult::WhiteWins,
+            Outcome::Decisive {
+                winner: Color::Black,
+            } => GameResult::BlackWins,
+            Outcome::Draw => GameResult::Draw,
+        }
+    }
+}
+
+impl GameResult {
+    fn white_points(self) -> f64 {
+        match self {
+            GameResult::WhiteWins => 1.0,
+            GameResult::BlackWins => 0.0,
+            GameResult::Draw => 0.5,
+        }
+    }
+}
+
+/// A scheduled game, with the players as indices into the participants.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Pairing {
+    white: usize,
+    black: usize,
+}
+
+/// Everything needed to continue a tournament after a reload.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Tournament {
+    format: TournamentFormat,
+    participants: Vec<MatchEngine>,
+    /// Every game in the order they are played
+    schedule: Vec<Pairing>,
+    /// Results of the first games of the schedule
+    results: Vec<GameResult>,
+    start_fen: String,
+}
+
+impl Tournament {
+    /// In a gauntlet the first participant plays all the others.
+    pub fn new(
+        format: TournamentFormat,
+        participants: Vec<MatchEngine>,
+        games_per_pairing: usize,
+        start_position: &Chess,
+    ) -> Self {
+        Self {
+            format,
+            schedule: schedule(format, participants.len(), games_per_pairing),
+            participants,
+            results: Vec::new(),
+            start_fen: Fen::from_position(start_position.clone(), shakmaty::EnPassantMode::Legal)
+                .to_string(),
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.results.len() >= self.schedule.len()
+    }
+
+    /// Finished games and their results.
+    fn games(&self) -> impl Iterator<Item = (Pairing, GameResult)> + '_ {
+        self.schedule
+            .iter()
+            .copied()
+            .zip(self.results.iter().copied())
+    }
+
+    /// Points and games played of `a` against `b`.
+    fn pairing_score(&self, a: usize, b: usize) -> (f64, usize) {
+        self.games()
+            .filter_map(|(pairing, result)| {
+                if pairing.white == a && pairing.black == b {
+                    Some(result.white_points())
+                } else if pairing.white == b && pairing.black == a {
+                    Some(1.0 - result.white_points())
+                } else {
+                    None
+                }
+            })
+            .fold((0.0, 0), |(points, games), p| (points + p, games + 1))
+    }
+
+    /// Points, wins, draws and losses of every participant.
+    fn standings(&self) -> Vec<Standing> {
+        standings(self.participants.len(), self.games())
+    }
+}
+
+/// Every game of a tournament between `players` participants, in the order they are played.
+fn schedule(format: TournamentFormat, players: usize, games_per_pairing: usize) -> Vec<Pairing> {
+    let pairs: Vec<(usize, usize)> = match format {
+        TournamentFormat::RoundRobin => (0..players)
+            .flat_map(|a| (a + 1..players).map(move |b| (a, b)))
+            .collect(),
+        TournamentFormat::Gauntlet => (1..players).map(|b| (0, b)).collect(),
+    };
+    // Every pairing plays its first game before any plays its second
+    (0..games_per_pairing)
+        .flat_map(|game| {
+            pairs.iter().map(move |&(a, b)| {
+                if game % 2 == 0 {
+                    Pairing { white: a, black: b }
+                } else {
+                    Pairing { white: b, black: a }
+                }
+            })
+        })
+        .collect()
+}
+
+/// Standings of `players` participants after `games`, best first.
+/// Participants with equal points keep their order.
+fn standings(players: usize, games: impl Iterator<Item = (Pairing, GameResult)>) -> Vec<Standing> {
+    let mut standings: Vec<Standing> = (0..players)
+        .map(|participant| Standing {
+            participant,
+            ..Default::default()
+        })
+        .collect();
+    for (pairing, result) in games {
+        for (player, points) in [
+            (pairing.white, result.white_points()),
+            (pairing.black, 1.0 - result.white_points()),
+        ] {
+            let standing = &mut standings[player];
+            standing.points += points;
+            if points > 0.75 {
+                standing.wins += 1;
+            } else if points > 0.25 {
+                standing.draws += 1;
+            } else {
+                standing.losses += 1;
+            }
+        }
+    }
+    standings.sort_by(|a, b| b.points.total_cmp(&a.points));
+    standings
+}
+
+/// A participant's row in the standings.
+#[derive(Default)]
+struct Standing {
+    participant: usize,
+    points: f64,
+    wins: usize,
+    draws: usize,
+    losses: usize,
+}
+
+/// Points as chess players write them, like 2½.
+fn format_points(points: f64) -> String {
+    let whole = points.floor();
+    match (whole as usize, points - whole >= 0.5) {
+        (0, true) => "½".to_string(),
+        (whole, true) => format!("{whole}½"),
+        (whole, false) => whole.to_string(),
+    }
+}
+
+/// Plays the games of a [`Tournament`] one after the other on the board.
+pub(crate) struct TournamentRunner {
+    pub tournament: Tournament,
+    /// Game indices are into the schedule
+    board_games: EngineGames,
+    paused: bool,
+}
+
+impl TournamentRunner {
+    /// A restored tournament starts paused, and continues when the user resumes it.
+    pub fn new(
+        tournament: Tournament,
+        paused: bool,
+        send_move_history: bool,
+        sender: mpsc::Sender<RequestLoopComm>,
+    ) -> Self {
+        Self {
+            tournament,
+            board_games: EngineGames::new(send_move_history, sender),
+            paused,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.paused && !self.tournament.is_finished()
+    }
+
+    /// Pause after abandoning the game on the board, which is played again on resuming.
+    pub fn pause(&mut self, board: &mut ChessBoard) {
+        if self.is_running() {
+            log::info!(
+                "Tournament paused after {} games",
+                self.tournament.results.len()
+            );
+            self.paused = true;
+            self.board_games.abandon(board);
+        }
+    }
+
+    /// Continue a paused tournament. The caller stops whatever else
+    /// is using the board first, as the next game replaces it.
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    /// Record the game on the board once it is over, and start the next one.
+    pub fn update(&mut self, board: &mut ChessBoard) {
+        if !self.is_running() {
+            return;
+        }
+        if !self.board_games.is_playing() {
+            self.start_game(board, self.tournament.results.len());
+            return;
+        }
+        let Some((index, termination)) = self.board_games.finished(board) else {
+            return;
+        };
+        debug_assert_eq!(index, self.tournament.results.len());
+        self.tournament
+            .results
+            .push(GameResult::from(termination.outcome()));
+    }
+
+    fn start_game(&mut self, board: &mut ChessBoard, index: usize) {
+        let pairing = self.tournament.schedule[index];
+        let participants = &self.tournament.participants;
+        let position: Chess = Fen::from_ascii(self.tournament.start_fen.as_bytes())
+            .ok()
+            .and_then(|fen| fen.into_position(CastlingMode::Standard).ok())
+            .unwrap_or_default();
+        self.board_games.start(
+            board,
+            index,
+            &participants[pairing.white],
+            &participants[pairing.black],
+            position,
+        );
+    }
+
+    /// Progress, standings and the crosstable.
+    /// Returns whether the user asked to resume, which is left to the caller.
+    pub fn show(&mut self, ui: &mut egui::Ui, board: &mut ChessBoard) -> bool {
+        let tournament = &self.tournament;
+        ui.heading(format!(
+            "{} with {} participants",
+            tournament.format,
+            tournament.participants.len()
+        ));
+        let (mut pause, mut resume) = (false, false);
+        ui.horizontal(|ui| {
+            let played = tournament.results.len();
+            let total = tournament.schedule.len();
+            if tournament.is_finished() {
+                ui.label(format!("Finished, {total} games played"));
+            } else if self.paused {
+                ui.label(format!("Paused after {played} of {total} games"));
+                resume = ui.button("▶ Resume").clicked();
+            } else {
+                let pairing = tournament.schedule[played];
+                ui.label(format!(
+                    "Game {} of {total}: {} vs {}",
+                    played + 1,
+                    tournament.participants[pairing.white].name,
+                    tournament.participants[pairing.black].name
+                ));
+                ui.spinner();
+                pause = ui.button("⏸ Pause").clicked();
+            }
+        });
+        if pause {
+            self.pause(board);
+        }
+
+        let tournament = &self.tournament;
+        ui.heading("Standings");
+        Grid::new("tournament_standings")
+            .striped(true)
+            .show(ui, |ui| {
+                for header in ["#", "Engine", "Points", "+", "=", "-"] {
+                    ui.label(RichText::new(header).strong());
+                }
+                ui.end_row();
+                for (rank, standing) in tournament.standings().iter().enumerate() {
+                    ui.label((rank + 1).to_string());
+                    ui.label(&tournament.participants[standing.participant].name);
+                    ui.label(format_points(standing.points));
+                    ui.label(standing.wins.to_string());
+                    ui.label(standing.draws.to_string());
+                    ui.label(standing.losses.to_string());
+                    ui.end_row();
+                }
+            });
+
+        ui.heading("Crosstable");
+        egui::ScrollArea::both().show(ui, |ui| {
+            Grid::new("tournament_crosstable")
+                .striped(true)
+                .show(ui, |ui| {
+                    let n = tournament.participants.len();
+                    ui.label("");
+                    for (b, participant) in tournament.participants.iter().enumerate() {
+                        ui.label(RichText::new((b + 1).to_string()).strong())
+                            .on_hover_text(&participant.name);
+                    }
+                    ui.end_row();
+                    for (a, participant) in tournament.participants.iter().enumerate() {
+                        ui.label(RichText::new(format!("{} {}", a + 1, participant.name)).strong());
+                        for b in 0..n {
+                            if a == b {
+                                ui.label("—");
+                                continue;
+                            }
+                            match tournament.pairing_score(a, b) {
+                                (_, 0) => ui.label(""),
+                                (points, games) => {
+                                    ui.label(format!("{}/{games}", format_points(points)))
+                                }
+                            };
+                        }
+                        ui.end_row();
+                    }
+                });
+        });
+        resume
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(schedule: &[Pairing]) -> Vec<(usize, usize)> {
+        schedule.iter().map(|p| (p.white, p.black)).collect()
+    }
+
+    #[test]
+    fn round_robin_plays_every_pairing_before_swapping_colors() {
+        let schedule = schedule(TournamentFormat::RoundRobin, 3, 2);
+        assert_eq!(
+            pairs(&schedule),
+            [(0, 1), (0, 2), (1, 2), (1, 0), (2, 0), (2, 1)]
+        );
+    }
+
+    #[test]
+    fn gauntlet_pairs_the_first_participant_with_everyone() {
+        let schedule = schedule(TournamentFormat::Gauntlet, 4, 3);
+        assert_eq!(
+            pairs(&schedule),
+            [
+                (0, 1),
+                (0, 2),
+                (0, 3),
+                (1, 0),
+                (2, 0),
+                (3, 0),
+                (0, 1),
+                (0, 2),
+                (0, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn no_games_without_opponents() {
+        assert!(schedule(TournamentFormat::RoundRobin, 1, 2).is_empty());
+        assert!(schedule(TournamentFormat::Gauntlet, 1, 2).is_empty());
+    }
+
+    #[test]
+    fn standings_count_points_and_keep_order_on_ties() {
+        let games = [
+            (Pairing { white: 0, black: 1 }, GameResult::WhiteWins),
+            (Pairing { white: 0, black: 2 }, GameResult::Draw),
+            (Pairing { white: 1, black: 2 }, GameResult::BlackWins),
+        ];
+        let standings = standings(3, games.into_iter());
+        let rows: Vec<_> = standings
+            .iter()
+            .map(|s| (s.participant, s.points, s.wins, s.draws, s.losses))
+            .collect();
+        assert_eq!(
+            rows,
+            [(0, 1.5, 1, 1, 0), (2, 1.5, 1, 1, 0), (1, 0.0, 0, 0, 2)]
+        );
+    }
+
+    #[test]
+    fn points_use_the_half_sign() {
+        assert_eq!(format_points(0.0), "0");
+        assert_eq!(format_points(0.5), "½");
+        assert_eq!(format_points(2.5), "2½");
+        assert_eq!(format_points(3.0), "3");
+    }
+}